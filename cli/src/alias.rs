@@ -0,0 +1,47 @@
+use anyhow::Result;
+use lib::{
+    alias::{add, list, remove},
+    options::Options,
+    settings::Settings,
+};
+use prettytable::{Cell, Row, Table};
+
+#[derive(clap::Subcommand, Debug)]
+pub enum AliasCommands {
+    /// Save a command under an alias, use $1, $2 for arguments that are given to the alias
+    #[command(trailing_var_arg = true)]
+    Add {
+        /// Name of the alias (example "gw-logs")
+        name: String,
+        /// Command you want to save (example "logs my-gateway --recent")
+        #[arg(required = true, allow_hyphen_values = true)]
+        command: Vec<String>,
+    },
+    /// Remove an alias
+    #[command(visible_alias = "rm")]
+    Remove { name: String },
+    /// List all the aliases you stored
+    #[command(visible_alias = "ls")]
+    List,
+}
+
+pub fn match_alias(options: &Options, alias_commands: &AliasCommands) -> Result<()> {
+    match alias_commands {
+        AliasCommands::Add { name, command } => add(options, name, command),
+        AliasCommands::Remove { name } => remove(options, name),
+        AliasCommands::List => {
+            let mut table = Table::new();
+            //HEADER
+            table.add_row(Row::new(vec![Cell::new("name"), Cell::new("command")]));
+            //CONTENT
+            for (name, command) in list(&Settings::load(options)?) {
+                table.add_row(Row::new(vec![
+                    Cell::new(&name),
+                    Cell::new(&command.join(" ")),
+                ]));
+            }
+            table.printstd();
+            Ok(())
+        }
+    }
+}
@@ -1,11 +1,23 @@
-use crate::{environment, subcommands::Subcommands};
-use anyhow::{Context, Result, bail};
+use crate::{alias, config, environment, history, plugins, subcommands::Subcommands};
+use anyhow::{bail, Context, Result};
 use clap::{CommandFactory, Parser};
 use clap_complete::{generate, Generator};
 use lib::{
-    cf::{login, check_if_cf_is_installed}, exec::exec, options::Options, settings::Settings,
+    cf::{check_if_cf_is_installed, login, login_dry_run},
+    doctor::{doctor, Check, Status},
+    exec::known_environments,
+    history::RecordingSink,
+    logs::{LogFilter, LogsRequest},
+    mcf::ExecRequest,
+    options::{default_cf_home, Options},
+    protection::confirm_protected_environments,
+    runbook::{protected_environments as runbook_protected_environments, run, Runbook, RunbookOutcome},
+    settings::Settings,
+    shell::shell,
+    sink::TerminalSink,
+    tui::tui,
 };
-use std::{io, path::PathBuf, sync::Arc};
+use std::{io, path::PathBuf, sync::Arc, time::Duration};
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
@@ -15,11 +27,11 @@ struct Mcf {
     #[command(subcommand)]
     command: Subcommands,
 
-    /// Overwrite mcf config path
+    /// Overwrite mcf config path (or set MCF_HOME)
     #[arg(long, global = true)]
     override_path: Option<String>,
 
-    /// Overwrite binary name for cloudfoundry cli (for example: "cf8")
+    /// Overwrite binary name for cloudfoundry cli (for example: "cf8", or set MCF_CF_BINARY)
     #[arg(long, global = true)]
     cf_binary_name: Option<String>,
 }
@@ -27,17 +39,37 @@ struct Mcf {
 pub async fn parse() -> Result<()> {
     let mcf: Mcf = Mcf::parse();
 
-    let options = Options::new(mcf.cf_binary_name, mcf.override_path);
+    let options = Options::new(mcf.cf_binary_name, mcf.override_path)?;
 
-    let settings: Settings = Settings::load(&options)?;
     match &mcf.command {
         Subcommands::Environment {
             environment_commands,
-        } => environment::match_environment(&settings, &options, environment_commands),
-        Subcommands::Login { name, sso_passcode, org, space } => {
-            if !check_if_cf_is_installed(&options.cf_binary_name)? {
-                bail!("mcf: could not find cf cli with binary name {}", &options.cf_binary_name);
-            };
+        } => environment::match_environment(&options, environment_commands),
+        Subcommands::Config { config_commands } => config::match_config(&options, config_commands),
+        Subcommands::Alias { alias_commands } => alias::match_alias(&options, alias_commands),
+        Subcommands::Plugins { plugins_commands } => plugins::match_plugins(&options, plugins_commands),
+        Subcommands::Login { name, sso_passcode, org, space, dry_run } => {
+            let settings: Settings = Settings::load(&options)?;
+            if *dry_run {
+                println!(
+                    "{}",
+                    login_dry_run(
+                        &settings,
+                        &options,
+                        name,
+                        &PathBuf::from(&options.mcf_home),
+                        sso_passcode,
+                        org,
+                        space
+                    )?
+                );
+                return Ok(());
+            }
+            check_if_cf_is_installed(
+                &settings,
+                &options,
+                &settings.get_environment_by_name(name).into_iter().collect::<Vec<_>>(),
+            )?;
             login(
                 &settings, 
                 &options, 
@@ -48,25 +80,106 @@ pub async fn parse() -> Result<()> {
                 space
             ).await
         }
-        Subcommands::Exec { names, command, sequential_mode } => {
-            if !check_if_cf_is_installed(&options.cf_binary_name)? {
-                bail!("mcf: could not find cf cli with binary name {}", &options.cf_binary_name);
+        Subcommands::Exec { names, command, sequential_mode, yes_i_mean_prod, dry_run, retry, retry_delay, .. } => {
+            let settings: Settings = Settings::load(&options)?;
+            let request = match names {
+                Some(names) => ExecRequest::new(names, command.iter().cloned()),
+                None => match lib::history::failed_from_last(&options, &settings.history)? {
+                    Some((run, request)) => {
+                        eprintln!(
+                            "mcf: executing \"{}\" again on {} that failed in run {}",
+                            run.command.join(" "),
+                            request.names(),
+                            run.id
+                        );
+                        request
+                    }
+                    None => {
+                        println!("mcf: nothing failed in the last run");
+                        return Ok(());
+                    }
+                },
             };
-            exec(
+            let sequential = *sequential_mode || request.is_sequential();
+            let request = request
+                .sequential(sequential)
+                .retries(*retry)
+                .retry_delay(Duration::from_secs(*retry_delay));
+            if *dry_run {
+                for line in lib::mcf::Mcf::new(settings, options).dry_run(&request)? {
+                    println!("{}", line);
+                }
+                return Ok(());
+            }
+            exec_request(&options, settings, &request, yes_i_mean_prod).await
+        }
+        Subcommands::Logs { names, app, recent, source, instance, since, until, window } => {
+            let settings: Settings = Settings::load(&options)?;
+            check_if_cf_is_installed(&settings, &options, &known_environments(names, &settings)?)?;
+            let filter = LogFilter { source_types: source.clone(), instances: instance.clone(), since: *since, until: *until };
+            let request = LogsRequest::new(names, app).recent(*recent).filter(filter).window(Duration::from_millis(*window));
+            lib::mcf::Mcf::new(settings, options.clone())
+                .original_cf_home(default_cf_home().context("Could not find home dir, set CF_HOME")?)
+                .logs(&request, &mut TerminalSink::new())
+                .await
+        }
+        Subcommands::History { history_commands } => history::match_history(&options, history_commands).await,
+        Subcommands::Run { runbook, names, yes_i_mean_prod } => {
+            let settings: Settings = Settings::load(&options)?;
+            let environments = known_environments(names, &settings)?;
+            check_if_cf_is_installed(&settings, &options, &environments)?;
+            let runbook = Runbook::load(runbook)?;
+            for (environment, command) in runbook_protected_environments(&settings, &environments, &runbook)? {
+                confirm_protected_environments(&settings, std::slice::from_ref(environment), &command, yes_i_mean_prod)?;
+            }
+            let outcomes = run(
+                &settings,
+                Arc::new(options.clone()),
+                names,
+                Arc::new(runbook),
+                Arc::new(default_cf_home().context("Could not find home dir, set CF_HOME")?),
+                Arc::new(PathBuf::from(&options.mcf_home)),
+            )
+            .await?;
+            print_runbook_summary(&outcomes)
+        }
+        Subcommands::Tui { names, command, yes_i_mean_prod } => {
+            let settings: Settings = Settings::load(&options)?;
+            let environments = known_environments(names, &settings)?;
+            check_if_cf_is_installed(&settings, &options, &environments)?;
+            confirm_protected_environments(&settings, &environments, command, yes_i_mean_prod)?;
+            tui(
                 &settings,
                 Arc::new(options.clone()),
                 names,
                 Arc::new(command.to_vec()),
-                Arc::new(
-                    dirs::home_dir()
-                        .context("Could not find home dir")?
-                        .join(".cf"),
-                ),
-                Arc::new(PathBuf::from(options.mcf_home)),
-                sequential_mode
+                Arc::new(default_cf_home().context("Could not find home dir, set CF_HOME")?),
+                Arc::new(PathBuf::from(&options.mcf_home)),
             )
             .await
         }
+        Subcommands::Shell { names, yes_i_mean_prod } => {
+            let settings: Settings = Settings::load(&options)?;
+            check_if_cf_is_installed(&settings, &options, &known_environments(names, &settings)?)?;
+            shell(
+                &settings,
+                Arc::new(options.clone()),
+                names,
+                Arc::new(default_cf_home().context("Could not find home dir, set CF_HOME")?),
+                Arc::new(PathBuf::from(&options.mcf_home)),
+                yes_i_mean_prod,
+            )
+            .await
+        }
+        Subcommands::Doctor => {
+            let checks = doctor(
+                &options,
+                &default_cf_home().context("Could not find home dir, set CF_HOME")?,
+                &PathBuf::from(&options.mcf_home),
+            )
+            .await;
+            print_doctor_checks(&checks)
+        }
         Subcommands::Completion { shell } => {
             let mut cmd = Mcf::command();
             eprintln!("Generating completion file for {:?}...", shell);
@@ -75,6 +188,74 @@ pub async fn parse() -> Result<()> {
     }
 }
 
+fn print_runbook_summary(outcomes: &[RunbookOutcome]) -> Result<()> {
+    println!("------------------ RUNBOOK SUMMARY ------------------");
+    for outcome in outcomes {
+        match (outcome.completed, outcome.failed_steps.is_empty()) {
+            (true, true) => println!("{}: completed", outcome.env_name),
+            (true, false) => println!(
+                "{}: completed, failed steps that could continue: {}",
+                outcome.env_name,
+                outcome.failed_steps.join(", ")
+            ),
+            (false, _) => println!(
+                "{}: stopped at failed step: {}",
+                outcome.env_name,
+                outcome.failed_steps.last().cloned().unwrap_or_default()
+            ),
+        }
+    }
+    let stopped = outcomes
+        .iter()
+        .filter(|outcome| !outcome.completed)
+        .map(|outcome| outcome.env_name.clone())
+        .collect::<Vec<String>>();
+    if !stopped.is_empty() {
+        bail!("mcf: runbook did not complete on {}", stopped.join(", "));
+    }
+    Ok(())
+}
+
+/// Execute the request after the checks and confirmations of "mcf exec", the run is recorded in
+/// the history
+pub async fn exec_request(
+    options: &Options,
+    settings: Settings,
+    request: &ExecRequest,
+    yes_i_mean_prod: &bool,
+) -> Result<()> {
+    let environments = known_environments(request.names(), &settings)?;
+    check_if_cf_is_installed(&settings, options, &environments)?;
+    confirm_protected_environments(&settings, &environments, request.command(), yes_i_mean_prod)?;
+    let history = settings.history.clone();
+    let mcf = lib::mcf::Mcf::new(settings, options.clone())
+        .original_cf_home(default_cf_home().context("Could not find home dir, set CF_HOME")?);
+    let mut terminal = TerminalSink::new();
+    if !history.enabled() {
+        return mcf.exec(request, &mut terminal).await;
+    }
+    let mut sink = RecordingSink::new(options, &history, request, &mut terminal);
+    let result = mcf.exec(request, &mut sink).await;
+    if let Some(error) = sink.error() {
+        eprintln!("mcf: could not record the run in the history: {:#}", error);
+    }
+    result
+}
+
+fn print_doctor_checks(checks: &[Check]) -> Result<()> {
+    for check in checks {
+        println!("[{}] {}: {}", check.status, check.name, check.message);
+        if let Some(hint) = &check.hint {
+            println!("       {}", hint);
+        }
+    }
+    let failed = checks.iter().filter(|check| check.status == Status::Fail).count();
+    if failed > 0 {
+        bail!("mcf: doctor found {} failed checks", failed);
+    }
+    Ok(())
+}
+
 fn print_completions<G: Generator>(gen: G, cmd: &mut clap::builder::Command) -> Result<()> {
     generate(gen, cmd, cmd.get_name().to_string(), &mut io::stdout());
     Ok(())
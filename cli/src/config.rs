@@ -0,0 +1,42 @@
+use anyhow::Result;
+use lib::{config::load, options::Options};
+use prettytable::{Cell, Row, Table};
+
+#[derive(clap::Subcommand, Debug)]
+pub enum ConfigCommands {
+    /// Show the merged configuration of the system, user and project (.mcf.yml) settings files
+    Show {
+        /// Show which settings file each value came from
+        #[arg(long)]
+        origin: bool,
+    },
+}
+
+pub fn match_config(options: &Options, config_commands: &ConfigCommands) -> Result<()> {
+    match config_commands {
+        ConfigCommands::Show { origin } => {
+            let layered_settings = load(options)?;
+            let mut table = Table::new();
+            //HEADER
+            let mut header = vec![Cell::new("key"), Cell::new("value")];
+            if *origin {
+                header.push(Cell::new("origin"));
+            }
+            table.add_row(Row::new(header));
+            //CONTENT
+            for value in layered_settings.origins {
+                let mut row = vec![Cell::new(&value.key), Cell::new(&value.value)];
+                if *origin {
+                    row.push(Cell::new(&format!(
+                        "{} ({})",
+                        value.layer,
+                        value.path.display()
+                    )));
+                }
+                table.add_row(Row::new(row));
+            }
+            table.printstd();
+            Ok(())
+        }
+    }
+}
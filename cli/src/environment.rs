@@ -1,6 +1,7 @@
 use anyhow::Result;
 use lib::{
-    environment::{add, list, remove, Environment},
+    cf::Version,
+    environment::{add, list, remove, restore, update, Environment},
     options::Options,
     settings::Settings,
 };
@@ -16,6 +17,57 @@ pub enum EnvironmentCommands {
         sso: bool,
         #[arg(long)]
         skip_ssl_validation: bool,
+        /// Cloudfoundry organization that is used on login when no org is given
+        #[arg(short, long)]
+        org: Option<String>,
+        /// Cloudfoundry space that is used on login when no space is given
+        #[arg(short, long)]
+        space: Option<String>,
+        /// Binary name for the cloudfoundry cli for this environment (for example: "cf7")
+        #[arg(long)]
+        cf_binary: Option<String>,
+        /// Oldest cloudfoundry cli version for this environment (for example: "8.5.0")
+        #[arg(long)]
+        cf_min_version: Option<Version>,
+        /// Environment variable for every cf command of this environment, the value can refer to
+        /// your own environment variables (for example: "HTTPS_PROXY=$CORPORATE_PROXY")
+        #[arg(long = "env", value_name = "KEY=VALUE", value_parser = parse_key_value)]
+        env: Vec<(String, String)>,
+        /// Require typing the environment name to confirm mutating commands (for example: delete, push)
+        #[arg(long)]
+        protected: bool,
+    },
+    /// Update an environment in the environment list (an empty value clears an optional field)
+    Update {
+        name: String,
+        #[arg(long)]
+        url: Option<String>,
+        #[arg(long)]
+        sso: Option<bool>,
+        #[arg(long)]
+        skip_ssl_validation: Option<bool>,
+        /// Cloudfoundry organization that is used on login when no org is given
+        #[arg(short, long)]
+        org: Option<String>,
+        /// Cloudfoundry space that is used on login when no space is given
+        #[arg(short, long)]
+        space: Option<String>,
+        /// Binary name for the cloudfoundry cli for this environment (for example: "cf7")
+        #[arg(long)]
+        cf_binary: Option<String>,
+        /// Oldest cloudfoundry cli version for this environment (for example: "8.5.0")
+        #[arg(long, value_parser = parse_min_version)]
+        cf_min_version: Option<String>,
+        /// Environment variable for every cf command of this environment, the value can refer to
+        /// your own environment variables (for example: "HTTPS_PROXY=$CORPORATE_PROXY")
+        #[arg(long = "env", value_name = "KEY=VALUE", value_parser = parse_key_value)]
+        env: Vec<(String, String)>,
+        /// Require typing the environment name to confirm mutating commands (for example: delete, push)
+        #[arg(long)]
+        protected: Option<bool>,
+        /// Remove an environment variable from this environment
+        #[arg(long, value_name = "KEY")]
+        unset_env: Vec<String>,
     },
     /// Remove an environment to the environment list
     #[command(visible_alias = "rm")]
@@ -23,10 +75,17 @@ pub enum EnvironmentCommands {
     /// List all the environment you stored
     #[command(visible_alias = "ls")]
     List,
+    /// Restore the environment list from a backup (defaults to the most recent backup)
+    Restore {
+        /// Name of the backup (example "settings-01700000000000000000.yml")
+        backup: Option<String>,
+        /// List the available backups instead of restoring one
+        #[arg(long)]
+        list: bool,
+    },
 }
 
 pub fn match_environment(
-    settings: &Settings,
     options: &Options,
     environment_commands: &EnvironmentCommands,
 ) -> Result<()> {
@@ -36,10 +95,84 @@ pub fn match_environment(
             url,
             sso,
             skip_ssl_validation,
-        } => add(settings, options, name, url, sso, skip_ssl_validation),
-        EnvironmentCommands::Remove { name } => remove(settings, options, name),
+            org,
+            space,
+            cf_binary,
+            cf_min_version,
+            env,
+            protected,
+        } => add(
+            options,
+            Environment {
+                name: name.clone(),
+                url: url.clone(),
+                sso: *sso,
+                skip_ssl_validation: *skip_ssl_validation,
+                org: org.clone(),
+                space: space.clone(),
+                cf_binary: cf_binary.clone(),
+                cf_min_version: cf_min_version.clone(),
+                env: env.iter().cloned().collect(),
+                protected: *protected,
+                isolated_plugins: false,
+            },
+        ),
+        EnvironmentCommands::Update {
+            name,
+            url,
+            sso,
+            skip_ssl_validation,
+            org,
+            space,
+            cf_binary,
+            cf_min_version,
+            env,
+            unset_env,
+            protected,
+        } => update(options, name, |environment| {
+            if let Some(some) = url {
+                environment.url = some.clone();
+            }
+            if let Some(some) = sso {
+                environment.sso = *some;
+            }
+            if let Some(some) = skip_ssl_validation {
+                environment.skip_ssl_validation = *some;
+            }
+            if let Some(some) = org {
+                environment.org = non_empty(some);
+            }
+            if let Some(some) = space {
+                environment.space = non_empty(some);
+            }
+            if let Some(some) = cf_binary {
+                environment.cf_binary = non_empty(some);
+            }
+            if let Some(some) = cf_min_version {
+                environment.cf_min_version = Version::parse(some).ok();
+            }
+            for key in unset_env {
+                environment.env.remove(key);
+            }
+            environment.env.extend(env.iter().cloned());
+            if let Some(some) = protected {
+                environment.protected = *some;
+            }
+        }),
+        EnvironmentCommands::Remove { name } => remove(options, name),
+        EnvironmentCommands::Restore { backup, list } => {
+            if *list {
+                for backup in Settings::list_backups(options)? {
+                    println!("{}", backup);
+                }
+            } else {
+                let restored = restore(options, backup)?;
+                println!("mcf: restored settings from backup {}", restored);
+            }
+            Ok(())
+        }
         EnvironmentCommands::List => {
-            let all_envs = list(settings);
+            let all_envs = list(&Settings::load(options)?);
             let mut table = Table::new();
             //HEADER
             table.add_row(Row::new(
@@ -60,3 +193,26 @@ pub fn match_environment(
         }
     }
 }
+
+fn non_empty(value: &str) -> Option<String> {
+    if value.is_empty() {
+        None
+    } else {
+        Some(value.to_string())
+    }
+}
+
+fn parse_min_version(value: &str) -> Result<String, String> {
+    if value.is_empty() || Version::parse(value).is_ok() {
+        Ok(value.to_string())
+    } else {
+        Err(format!("expected a version like 8.5.0 but got {:?}", value))
+    }
+}
+
+fn parse_key_value(value: &str) -> Result<(String, String), String> {
+    match value.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+        _ => Err(format!("expected KEY=VALUE but got {:?}", value)),
+    }
+}
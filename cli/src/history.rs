@@ -0,0 +1,113 @@
+use anyhow::Result;
+use lib::{
+    history::{get, list, local_time, output, Run},
+    mcf::ExecRequest,
+    options::Options,
+    settings::Settings,
+};
+use prettytable::{Cell, Row, Table};
+
+use crate::cli::exec_request;
+
+#[derive(clap::Subcommand, Debug)]
+pub enum HistoryCommands {
+    /// List the recorded runs of mcf exec, the newest last
+    #[command(visible_alias = "ls")]
+    List,
+    /// Show the command, the exit codes and the output of a run
+    Show {
+        /// Id of the run, "last" is the newest run
+        id: String,
+    },
+    /// Execute the command of a run again on the same environments
+    Rerun {
+        /// Id of the run, "last" is the newest run
+        id: String,
+        /// Skip the confirmation for protected environments (required without a terminal)
+        #[arg(long)]
+        yes_i_mean_prod: bool,
+    },
+}
+
+pub async fn match_history(options: &Options, history_commands: &HistoryCommands) -> Result<()> {
+    match history_commands {
+        HistoryCommands::List => print_list(options),
+        HistoryCommands::Show { id } => print_run(options, &get(options, id)?),
+        HistoryCommands::Rerun {
+            id,
+            yes_i_mean_prod,
+        } => {
+            let run = get(options, id)?;
+            let request = ExecRequest::new(run.environments.join(","), run.command.iter().cloned())
+                .sequential(run.sequential);
+            eprintln!(
+                "mcf: rerun of {}: mcf exec {} {}",
+                run.id,
+                request.names(),
+                run.command.join(" ")
+            );
+            exec_request(options, Settings::load(options)?, &request, yes_i_mean_prod).await
+        }
+    }
+}
+
+fn print_list(options: &Options) -> Result<()> {
+    let mut table = Table::new();
+    //HEADER
+    table.add_row(Row::new(vec![
+        Cell::new("id"),
+        Cell::new("started"),
+        Cell::new("environments"),
+        Cell::new("command"),
+        Cell::new("result"),
+    ]));
+    //CONTENT
+    for run in list(options)? {
+        table.add_row(Row::new(vec![
+            Cell::new(&run.id),
+            Cell::new(&local_time(&run.started)),
+            Cell::new(&run.environments.join(",")),
+            Cell::new(&run.command.join(" ")),
+            Cell::new(&result(&run)),
+        ]));
+    }
+    table.printstd();
+    Ok(())
+}
+
+fn print_run(options: &Options, run: &Run) -> Result<()> {
+    println!("id:           {}", run.id);
+    println!("started:      {}", local_time(&run.started));
+    if let Some(finished) = &run.finished {
+        println!("finished:     {}", local_time(finished));
+    }
+    println!("environments: {}", run.environments.join(","));
+    println!("command:      {}", run.command.join(" "));
+    println!("result:       {}", result(run));
+    for env_name in &run.environments {
+        let exit_code = match run.exit_codes.get(env_name) {
+            Some(Some(code)) => format!("exit code {}", code),
+            Some(None) => String::from("killed"),
+            None => String::from("did not finish"),
+        };
+        println!(
+            "------------------ ENVIRONMENT {} ({}) ------------------",
+            env_name, exit_code
+        );
+        if !run.output_recorded() {
+            println!("mcf: the output is not recorded, cf used the terminal");
+        } else {
+            print!("{}", output(options, run, env_name)?);
+        }
+    }
+    Ok(())
+}
+
+fn result(run: &Run) -> String {
+    let failed = run.failed_environments();
+    match (&run.finished, failed.is_empty()) {
+        (None, _) => String::from("did not finish"),
+        (Some(_), true) => String::from("ok"),
+        (Some(_), false) => format!("failed on {}", failed.join(",")),
+    }
+}
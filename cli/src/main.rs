@@ -1,10 +1,30 @@
+mod alias;
 mod cli;
+mod config;
 mod environment;
+mod history;
+mod plugins;
 mod subcommands;
 extern crate log;
 
+use lib::error::McfError;
+
 #[tokio::main]
 async fn main() -> Result<(), anyhow::Error> {
     env_logger::init();
-    cli::parse().await
+    match cli::parse().await {
+        Err(error) if McfError::find(&error).is_some() => {
+            print_error(&error);
+            std::process::exit(1);
+        }
+        result => result,
+    }
+}
+
+/// An mcf error is something the user can fix, it is printed with a hint instead of a backtrace
+fn print_error(error: &anyhow::Error) {
+    eprintln!("Error: {:#}", error);
+    if let Some(hint) = McfError::find(error).and_then(McfError::hint) {
+        eprintln!("hint: {}", hint);
+    }
 }
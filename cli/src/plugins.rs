@@ -0,0 +1,73 @@
+use anyhow::{Context, Result};
+use lib::{
+    options::{default_cf_home, Options},
+    plugins::{isolate, list, share, sync},
+    settings::Settings,
+};
+use prettytable::{Cell, Row, Table};
+use std::path::{Path, PathBuf};
+
+#[derive(clap::Subcommand, Debug)]
+pub enum PluginsCommands {
+    /// List the plugin directory and the registered plugins of every environment
+    #[command(visible_alias = "ls")]
+    List,
+    /// Link every environment that is not isolated to the shared plugins (~/.cf/plugins), own
+    /// plugin directories of these environments are removed after a confirmation
+    Sync {
+        /// Remove own plugin directories without a confirmation
+        #[arg(long)]
+        force: bool,
+    },
+    /// Give an environment its own plugins, it starts with a copy of the shared plugins
+    Isolate {
+        /// Name of the environment (example "cf-dev")
+        name: String,
+    },
+    /// Use the shared plugins again for an isolated environment, its own plugins are removed
+    Share {
+        /// Name of the environment (example "cf-dev")
+        name: String,
+    },
+}
+
+pub fn match_plugins(options: &Options, plugins_commands: &PluginsCommands) -> Result<()> {
+    let settings = Settings::load(options)?;
+    let original_cf_home = default_cf_home().context("Could not find home dir, set CF_HOME")?;
+    let mcf_folder = PathBuf::from(&options.mcf_home);
+    match plugins_commands {
+        PluginsCommands::List => print_list(&settings, &original_cf_home, &mcf_folder),
+        PluginsCommands::Sync { force } => {
+            sync(&settings, &original_cf_home, &mcf_folder, force)?;
+            print_list(&settings, &original_cf_home, &mcf_folder)
+        }
+        PluginsCommands::Isolate { name } => {
+            isolate(options, &settings, name, &original_cf_home, &mcf_folder)
+        }
+        PluginsCommands::Share { name } => {
+            share(options, &settings, name, &original_cf_home, &mcf_folder)
+        }
+    }
+}
+
+fn print_list(settings: &Settings, original_cf_home: &Path, mcf_folder: &Path) -> Result<()> {
+    let mut table = Table::new();
+    //HEADER
+    table.add_row(Row::new(vec![
+        Cell::new("name"),
+        Cell::new("plugins"),
+        Cell::new("registered"),
+        Cell::new("directory"),
+    ]));
+    //CONTENT
+    for plugins in list(settings, original_cf_home, mcf_folder) {
+        table.add_row(Row::new(vec![
+            Cell::new(&plugins.env_name),
+            Cell::new(plugins.state.as_ref()),
+            Cell::new(&plugins.registered.join(", ")),
+            Cell::new(&plugins.directory.to_string_lossy()),
+        ]));
+    }
+    table.printstd();
+    Ok(())
+}
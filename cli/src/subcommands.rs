@@ -1,6 +1,13 @@
+use crate::alias::AliasCommands;
+use crate::config::ConfigCommands;
 use crate::environment::EnvironmentCommands;
+use crate::history::HistoryCommands;
+use crate::plugins::PluginsCommands;
 use clap::Subcommand;
 use clap_complete::Shell;
+use lib::history::Timestamp;
+use lib::logs::parse_time;
+use std::path::PathBuf;
 
 #[derive(Subcommand, Debug)]
 pub enum Subcommands {
@@ -10,6 +17,21 @@ pub enum Subcommands {
         #[command(subcommand)]
         environment_commands: EnvironmentCommands,
     },
+    /// Show the configuration that is merged from all settings files
+    Config {
+        #[command(subcommand)]
+        config_commands: ConfigCommands,
+    },
+    /// Add, Remove, List aliases for commands you often execute
+    Alias {
+        #[command(subcommand)]
+        alias_commands: AliasCommands,
+    },
+    /// List, Sync, Isolate the cf plugins of the environments
+    Plugins {
+        #[command(subcommand)]
+        plugins_commands: PluginsCommands,
+    },
     /// Login to one of the Cloud Foundry environments
     #[command(visible_alias = "l")]
     Login {
@@ -24,18 +46,99 @@ pub enum Subcommands {
          /// Cloudfoundry space
         #[arg(short,long)]
         space: Option<String>,
+        /// Print the cf command line without executing it
+        #[arg(long)]
+        dry_run: bool,
     },
     /// Execute command on Cloud Foundry environment
     #[command(visible_alias = "e", trailing_var_arg = true)]
     Exec {
-        /// Names of the environments (example "cf-dev,cf-prod")
-        names: String,
-        /// Command you want to execute (example "logs your-application --recent")
+        /// Names of the environments (example "cf-dev,cf-prod" or "all")
+        #[arg(required_unless_present = "failed_from_last")]
+        names: Option<String>,
+        /// Command or alias you want to execute (example "logs your-application --recent")
         command: Vec<String>,
         /// Execute command sequentially (example "ssh your-application")
         #[arg(short, long)]
-        sequential_mode: bool
+        sequential_mode: bool,
+        /// Skip the confirmation for protected environments (required without a terminal)
+        #[arg(long)]
+        yes_i_mean_prod: bool,
+        /// Print the cf command line for every environment without executing anything
+        #[arg(long)]
+        dry_run: bool,
+        /// Execute the command again on an environment when cf exits with an error, at most this many times
+        #[arg(long, value_name = "N", default_value_t = 0)]
+        retry: u32,
+        /// Seconds to wait before the first retry, the wait doubles with every retry
+        #[arg(long, value_name = "SECONDS", default_value_t = 5)]
+        retry_delay: u64,
+        /// Execute the command of the last run again on the environments on which it failed
+        #[arg(long, conflicts_with_all = ["names", "command"])]
+        failed_from_last: bool,
+    },
+    /// Follow the logs of an app on Cloud Foundry environments merged in timestamp order
+    Logs {
+        /// Names of the environments (example "cf-dev,cf-prod" or "all")
+        names: String,
+        /// Name of the app (example "test-service")
+        app: String,
+        /// Show the recent logs instead of following the logs
+        #[arg(long)]
+        recent: bool,
+        /// Only show these source types (example "RTR,APP", "APP" includes "APP/PROC/WEB")
+        #[arg(long, value_name = "TYPES", value_delimiter = ',')]
+        source: Vec<String>,
+        /// Only show the lines of these instance indexes (example "0,1")
+        #[arg(long, value_name = "INDEXES", value_delimiter = ',')]
+        instance: Vec<u32>,
+        /// Only show lines from this time on (example "2022-09-02T15:53:16+02:00", "2022-09-02 15:53" or "10m" for 10 minutes ago)
+        #[arg(long, value_name = "TIME", value_parser = parse_time)]
+        since: Option<Timestamp>,
+        /// Only show lines up to this time (same formats as --since)
+        #[arg(long, value_name = "TIME", value_parser = parse_time)]
+        until: Option<Timestamp>,
+        /// Milliseconds a line is held back so that older lines of slower environments can go in front of it
+        #[arg(long, value_name = "MILLISECONDS", default_value_t = 1000)]
+        window: u64,
+    },
+    /// List, Show, Rerun the recorded runs of mcf exec, the output is recorded unless cf used the
+    /// terminal (an interactive command in sequential mode)
+    History {
+        #[command(subcommand)]
+        history_commands: HistoryCommands,
+    },
+    /// Execute the steps of a runbook on Cloud Foundry environments
+    Run {
+        /// Path to the runbook (example "release.yml")
+        runbook: PathBuf,
+        /// Names of the environments (example "cf-dev,cf-prod")
+        names: String,
+        /// Skip the confirmation for protected environments (required without a terminal)
+        #[arg(long)]
+        yes_i_mean_prod: bool,
+    },
+    /// Show the output of a command in a pane per Cloud Foundry environment
+    Tui {
+        /// Names of the environments (example "cf-dev,cf-prod")
+        names: String,
+        /// Command or alias you want to execute after "--" (example "-- logs your-application")
+        #[arg(last = true, required = true)]
+        command: Vec<String>,
+        /// Skip the confirmation for protected environments (required without a terminal)
+        #[arg(long)]
+        yes_i_mean_prod: bool,
+    },
+    /// Open a prompt that executes every entered command on Cloud Foundry environments
+    Shell {
+        /// Names of the environments (example "cf-dev,cf-prod")
+        names: String,
+        /// Skip the confirmation for protected environments
+        #[arg(long)]
+        yes_i_mean_prod: bool,
     },
+    /// Check the cf cli, settings, plugins, homes and sessions of the environments
+    Doctor,
     /// Generate shell autocompletion files
     Completion {
         #[arg(value_enum)]
@@ -6,27 +6,35 @@ fn can_run_mcf() {
     let mut cmd = Command::cargo_bin("mcf").unwrap();
     cmd.arg("-h");
     cmd.assert().success();
-    let expected_output = format!(
-        r###"Usage: mcf [OPTIONS] <COMMAND>
+    let expected_output = r###"Usage: mcf [OPTIONS] <COMMAND>
 
 Commands:
   environment  Add, Remove, List environment (example cf-dev) [aliases: env]
+  config       Show the configuration that is merged from all settings files
+  alias        Add, Remove, List aliases for commands you often execute
+  plugins      List, Sync, Isolate the cf plugins of the environments
   login        Login to one of the Cloud Foundry environments [aliases: l]
   exec         Execute command on Cloud Foundry environment [aliases: e]
+  logs         Follow the logs of an app on Cloud Foundry environments merged in timestamp order
+  history      List, Show, Rerun the recorded runs of mcf exec
+  run          Execute the steps of a runbook on Cloud Foundry environments
+  tui          Show the output of a command in a pane per Cloud Foundry environment
+  shell        Open a prompt that executes every entered command on Cloud Foundry environments
+  doctor       Check the cf cli, settings, plugins, homes and sessions of the environments
   completion   Generate shell autocompletion files
   help         Print this message or the help of the given subcommand(s)
 
 Options:
       --override-path <OVERRIDE_PATH>
-          Overwrite mcf config path
+          Overwrite mcf config path (or set MCF_HOME)
       --cf-binary-name <CF_BINARY_NAME>
-          Overwrite binary name for cloudfoundry cli (for example: "cf8")
+          Overwrite binary name for cloudfoundry cli (for example: "cf8", or set MCF_CF_BINARY)
   -h, --help
           Print help
   -V, --version
           Print version
 "###
-    );
+    .to_string();
     let actual_output = String::from_utf8(cmd.assert().get_output().to_owned().stdout).unwrap();
     assert!(actual_output.contains(&expected_output));
 }
@@ -36,7 +44,7 @@ Options:
 fn can_run_login() {
     let url = "http://localhost:8080";
     let mut add_env = Command::cargo_bin("mcf").unwrap();
-    add_env.args(&[
+    add_env.args([
         "env",
         "add",
         "wiremock",
@@ -46,7 +54,7 @@ fn can_run_login() {
     ]);
     add_env.assert().success();
     let mut login = Command::cargo_bin("mcf").unwrap();
-    login.args(&[
+    login.args([
         "login",
         "wiremock",
         "--sso-passcode",
@@ -80,7 +88,7 @@ space:          team-space
 #[test]
 fn can_run_exec() {
     let mut add_env = Command::cargo_bin("mcf").unwrap();
-    add_env.args(&[
+    add_env.args([
         "env",
         "add",
         "wiremock",
@@ -90,7 +98,7 @@ fn can_run_exec() {
     ]);
     add_env.assert().success();
     let mut login = Command::cargo_bin("mcf").unwrap();
-    login.args(&[
+    login.args([
         "login",
         "wiremock",
         "--sso-passcode",
@@ -100,7 +108,7 @@ fn can_run_exec() {
     ]);
     login.assert().success();
     let mut cmd = Command::cargo_bin("mcf").unwrap();
-    cmd.args(&["exec", "wiremock", "apps"]);
+    cmd.args(["exec", "wiremock", "apps"]);
     cmd.assert().success();
     let expected_output = r###"wiremock | Getting apps in org cf-services / space team-space as email@company.com...
 wiremock | 
@@ -0,0 +1,200 @@
+use crate::options::Options;
+use crate::settings::Settings;
+use anyhow::{bail, Result};
+use std::collections::BTreeMap;
+
+pub fn validate_name(name: &str) -> Result<()> {
+    if name.is_empty() {
+        bail!("alias name can not be empty");
+    }
+    if name.starts_with('-') {
+        bail!("alias name {:?} can not start with '-'", name);
+    }
+    if name.chars().any(char::is_whitespace) {
+        bail!("alias name {:?} can not contain whitespace", name);
+    }
+    Ok(())
+}
+
+pub fn add(options: &Options, name: &String, command: &[String]) -> Result<()> {
+    validate_name(name)?;
+    if command.is_empty() {
+        bail!("alias {:?} needs a command", name);
+    }
+    Settings::update(options, |settings| {
+        settings.aliases.insert(name.clone(), command.to_vec());
+        Ok(())
+    })
+}
+
+pub fn remove(options: &Options, name: &String) -> Result<()> {
+    Settings::update(options, |settings| {
+        settings.aliases.remove(name);
+        Ok(())
+    })
+}
+
+pub fn list(settings: &Settings) -> BTreeMap<String, Vec<String>> {
+    settings.aliases.clone()
+}
+
+/// Replace an alias at the start of the command with its saved command. Placeholders like $1 are
+/// replaced with the arguments after the alias, arguments that are not used by a placeholder are
+/// appended to the end.
+pub fn expand(settings: &Settings, command: &[String]) -> Result<Vec<String>> {
+    let Some((alias, saved_command)) = command
+        .first()
+        .and_then(|first| settings.aliases.get_key_value(first))
+    else {
+        return Ok(command.to_vec());
+    };
+    let arguments = &command[1..];
+    let mut used = vec![false; arguments.len()];
+    let mut result = Vec::new();
+    for part in saved_command {
+        result.push(replace_placeholders(alias, part, arguments, &mut used)?);
+    }
+    result.extend(
+        arguments
+            .iter()
+            .zip(used)
+            .filter(|(_, used)| !used)
+            .map(|(argument, _)| argument.clone()),
+    );
+    Ok(result)
+}
+
+fn replace_placeholders(
+    alias: &str,
+    part: &str,
+    arguments: &[String],
+    used: &mut [bool],
+) -> Result<String> {
+    let mut result = String::new();
+    let mut chars = part.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '$' {
+            result.push(c);
+            continue;
+        }
+        let mut digits = String::new();
+        while let Some(digit) = chars.peek().filter(|c| c.is_ascii_digit()) {
+            digits.push(*digit);
+            chars.next();
+        }
+        match digits.parse::<usize>() {
+            Ok(index) if index >= 1 => {
+                let Some(argument) = arguments.get(index - 1) else {
+                    bail!(
+                        "alias {:?} expects at least {} argument(s) but got {}",
+                        alias,
+                        index,
+                        arguments.len()
+                    );
+                };
+                used[index - 1] = true;
+                result.push_str(argument);
+            }
+            _ => {
+                result.push('$');
+                result.push_str(&digits);
+            }
+        }
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use tempfile::tempdir;
+
+    fn settings() -> Settings {
+        Settings {
+            aliases: BTreeMap::from([
+                (
+                    String::from("gw-logs"),
+                    vec![
+                        String::from("logs"),
+                        String::from("my-gateway"),
+                        String::from("--recent"),
+                    ],
+                ),
+                (
+                    String::from("app"),
+                    vec![String::from("curl"), String::from("/v3/apps?names=$1")],
+                ),
+            ]),
+            ..Default::default()
+        }
+    }
+
+    fn strings(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+
+    #[test]
+    fn test_expand_without_alias() {
+        assert_eq!(
+            expand(&settings(), &strings(&["apps"])).unwrap(),
+            strings(&["apps"])
+        );
+    }
+
+    #[test]
+    fn test_expand_appends_extra_arguments() {
+        assert_eq!(
+            expand(&settings(), &strings(&["gw-logs", "--verbose"])).unwrap(),
+            strings(&["logs", "my-gateway", "--recent", "--verbose"])
+        );
+    }
+
+    #[test]
+    fn test_expand_replaces_placeholders() {
+        assert_eq!(
+            expand(&settings(), &strings(&["app", "my-app", "-v"])).unwrap(),
+            strings(&["curl", "/v3/apps?names=my-app", "-v"])
+        );
+    }
+
+    #[test]
+    fn test_expand_fails_on_missing_argument() {
+        let result = expand(&settings(), &strings(&["app"]));
+        assert!(result.is_err());
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "alias \"app\" expects at least 1 argument(s) but got 0"
+        );
+    }
+
+    #[test]
+    fn test_add_and_remove() {
+        let tempdir: PathBuf = tempdir().unwrap().keep();
+        let options = Options {
+            cf_binary_name: String::from("cf"),
+            mcf_home: tempdir.to_str().unwrap().to_string(),
+        };
+        let result = add(
+            &options,
+            &String::from("gw-logs"),
+            &strings(&["logs", "gw"]),
+        );
+        assert!(result.is_ok());
+        assert_eq!(
+            std::fs::read_to_string(tempdir.join("settings.yml")).unwrap(),
+            "environments: []\naliases:\n  gw-logs:\n  - logs\n  - gw\n"
+        );
+        let result = remove(&options, &String::from("gw-logs"));
+        assert!(result.is_ok());
+        assert!(Settings::load_user(&options).unwrap().aliases.is_empty());
+    }
+
+    #[test]
+    fn test_validate_name() {
+        assert!(validate_name("gw-logs").is_ok());
+        assert!(validate_name("").is_err());
+        assert!(validate_name("-x").is_err());
+        assert!(validate_name("a b").is_err());
+    }
+}
@@ -0,0 +1,157 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::cf::{cf_version, Version};
+use crate::settings::write_atomically;
+
+const CACHE_FILE_NAME: &str = "cf_versions.yml";
+
+/// The version of a cf binary, valid as long as the binary has the same size and modification time
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone)]
+struct CachedVersion {
+    size: u64,
+    modified: u64,
+    version: Version,
+}
+
+/// The version of a cf binary from the cache in mcf_home, cf is only executed when the binary is
+/// not in the cache or when it changed since it was cached
+pub(crate) fn cached_cf_version(mcf_home: &Path, cf_binary_name: &String) -> Result<Version> {
+    let Some((path, size, modified)) = resolve_binary(cf_binary_name) else {
+        return cf_version(cf_binary_name);
+    };
+    let cache_path = mcf_home.join(CACHE_FILE_NAME);
+    let mut cache = read_cache(&cache_path);
+    let key = path.to_string_lossy().to_string();
+    if let Some(some) = cache
+        .get(&key)
+        .filter(|cached| cached.size == size && cached.modified == modified)
+    {
+        return Ok(some.version.clone());
+    }
+    let version = cf_version(cf_binary_name)?;
+    cache.insert(
+        key,
+        CachedVersion {
+            size,
+            modified,
+            version: version.clone(),
+        },
+    );
+    // the cache only saves time, a cache that can not be written is not a reason to fail
+    let _ = write_cache(&cache_path, &cache);
+    Ok(version)
+}
+
+/// The path of a binary like the shell would find it, with its size and modification time
+fn resolve_binary(cf_binary_name: &String) -> Option<(PathBuf, u64, u64)> {
+    let name = Path::new(cf_binary_name);
+    let candidates = if name.components().count() > 1 {
+        vec![name.to_path_buf()]
+    } else {
+        std::env::split_paths(&std::env::var_os("PATH")?)
+            .flat_map(|dir| executable_names(cf_binary_name).map(move |name| dir.join(name)))
+            .collect()
+    };
+    let path = candidates
+        .into_iter()
+        .find(|candidate| candidate.is_file())?
+        .canonicalize()
+        .ok()?;
+    let metadata = std::fs::metadata(&path).ok()?;
+    let modified = metadata
+        .modified()
+        .ok()?
+        .duration_since(UNIX_EPOCH)
+        .ok()?
+        .as_nanos() as u64;
+    Some((path, metadata.len(), modified))
+}
+
+#[cfg(target_os = "windows")]
+fn executable_names(cf_binary_name: &str) -> impl Iterator<Item = String> + '_ {
+    [".exe", ".bat", ".cmd", ""]
+        .into_iter()
+        .map(move |extension| format!("{}{}", cf_binary_name, extension))
+}
+
+#[cfg(not(target_os = "windows"))]
+fn executable_names(cf_binary_name: &str) -> impl Iterator<Item = String> + '_ {
+    std::iter::once(cf_binary_name.to_string())
+}
+
+fn read_cache(path: &Path) -> BTreeMap<String, CachedVersion> {
+    std::fs::read_to_string(path)
+        .ok()
+        .and_then(|content| serde_yaml::from_str(&content).ok())
+        .unwrap_or_default()
+}
+
+fn write_cache(path: &Path, cache: &BTreeMap<String, CachedVersion>) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    write_atomically(path, &serde_yaml::to_string(cache)?)
+}
+
+#[cfg(all(test, not(target_os = "windows")))]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+    use tempfile::tempdir;
+
+    fn fake_cf(path: &Path, version: &str) {
+        std::fs::write(
+            path,
+            format!(
+                "#!/bin/sh\necho \"cf version {}, Cloud Foundry command line tool\"\n",
+                version
+            ),
+        )
+        .unwrap();
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o755)).unwrap();
+    }
+
+    #[test]
+    fn test_cached_cf_version() {
+        let tempdir: PathBuf = tempdir().unwrap().keep();
+        let cf = tempdir.join("cf");
+        let cf_binary_name = cf.to_string_lossy().to_string();
+        let mcf_home = tempdir.join("mcf");
+        fake_cf(&cf, "8.7.0");
+        assert_eq!(
+            cached_cf_version(&mcf_home, &cf_binary_name).unwrap(),
+            Version::new(8, 7, 0)
+        );
+        // a cached version is used without executing the binary
+        let cache_path = mcf_home.join(CACHE_FILE_NAME);
+        let mut cache = read_cache(&cache_path);
+        assert_eq!(cache.len(), 1);
+        cache
+            .values_mut()
+            .for_each(|cached| cached.version = Version::new(8, 0, 0));
+        write_cache(&cache_path, &cache).unwrap();
+        assert_eq!(
+            cached_cf_version(&mcf_home, &cf_binary_name).unwrap(),
+            Version::new(8, 0, 0)
+        );
+        // a changed binary is executed again
+        fake_cf(&cf, "8.10.0");
+        assert_eq!(
+            cached_cf_version(&mcf_home, &cf_binary_name).unwrap(),
+            Version::new(8, 10, 0)
+        );
+    }
+
+    #[test]
+    fn test_cached_cf_version_of_missing_binary() {
+        let tempdir: PathBuf = tempdir().unwrap().keep();
+        let result = cached_cf_version(&tempdir, &String::from("mcf-binary-that-does-not-exist"));
+        assert!(result.is_err());
+        assert!(!tempdir.join(CACHE_FILE_NAME).exists());
+    }
+}
@@ -0,0 +1,369 @@
+use std::path::Path;
+use std::process::Stdio;
+use std::time::Duration;
+
+use anyhow::Result;
+use strum::{AsRefStr, Display};
+
+use crate::cf::{cf_command_tokio, cf_version, check_if_cf_is_installed};
+use crate::environment::Environment;
+use crate::error::McfError;
+use crate::options::Options;
+use crate::plugins::{plugin_directory, state, PluginState};
+use crate::settings::Settings;
+
+/// How long cf may take to get an oauth token, an unreachable environment would hang the doctor
+/// otherwise
+pub const SESSION_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// The outcome of a check, only a failed check makes the doctor fail
+#[derive(Debug, Clone, Copy, PartialEq, Eq, AsRefStr, Display)]
+#[strum(serialize_all = "lowercase")]
+pub enum Status {
+    Pass,
+    Warn,
+    Fail,
+}
+
+/// The outcome of a single check with a hint on how to fix it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Check {
+    pub name: String,
+    pub status: Status,
+    pub message: String,
+    pub hint: Option<String>,
+}
+
+impl Check {
+    fn pass(name: String, message: String) -> Check {
+        Check {
+            name,
+            status: Status::Pass,
+            message,
+            hint: None,
+        }
+    }
+
+    fn warn(name: String, message: String, hint: String) -> Check {
+        Check {
+            name,
+            status: Status::Warn,
+            message,
+            hint: Some(hint),
+        }
+    }
+
+    fn fail(name: String, message: String, hint: String) -> Check {
+        Check {
+            name,
+            status: Status::Fail,
+            message,
+            hint: Some(hint),
+        }
+    }
+}
+
+/// Check the settings, the cf cli and the plugins, home and session of every environment
+pub async fn doctor(options: &Options, original_cf_home: &Path, mcf_folder: &Path) -> Vec<Check> {
+    let (settings_check, settings) = check_settings(options);
+    let mut checks = vec![settings_check];
+    let settings = settings.unwrap_or_default();
+    let environments = &settings.environments;
+    let mut cf_binary_names = environments
+        .iter()
+        .map(|env| env.get_cf_binary_name(options).clone())
+        .collect::<Vec<String>>();
+    if cf_binary_names.is_empty() {
+        cf_binary_names.push(options.cf_binary_name.clone());
+    }
+    cf_binary_names.sort();
+    cf_binary_names.dedup();
+    let mut missing_cf_binary_names = Vec::new();
+    for cf_binary_name in cf_binary_names {
+        let check = check_cf(&settings, options, &cf_binary_name);
+        if check.status == Status::Fail {
+            missing_cf_binary_names.push(cf_binary_name);
+        }
+        checks.push(check);
+    }
+    checks.push(check_home(
+        String::from("mcf home"),
+        &options.get_mcf_home_path_buf(),
+    ));
+    for environment in environments {
+        checks.push(check_plugins(environment, original_cf_home, mcf_folder));
+        let home = mcf_folder.join("homes").join(&environment.name);
+        checks.push(check_home(format!("{} home", environment.name), &home));
+        if !missing_cf_binary_names.contains(environment.get_cf_binary_name(options)) {
+            checks.push(check_session(options, environment, mcf_folder).await);
+        }
+    }
+    checks
+}
+
+fn check_settings(options: &Options) -> (Check, Option<Settings>) {
+    let name = String::from("settings");
+    match Settings::load(options) {
+        Ok(settings) => (
+            Check::pass(
+                name,
+                format!("{} environments", settings.environments.len()),
+            ),
+            Some(settings),
+        ),
+        Err(error) => (
+            Check::fail(
+                name,
+                format!("{:#}", error),
+                hint(&error, "fix the settings file or run \"mcf env restore\""),
+            ),
+            None,
+        ),
+    }
+}
+
+fn check_cf(settings: &Settings, options: &Options, cf_binary_name: &String) -> Check {
+    let name = format!("cf cli {}", cf_binary_name);
+    let version = match cf_version(cf_binary_name) {
+        Ok(some) => some,
+        Err(error) => {
+            return Check::fail(
+                name,
+                format!("{:#}", error),
+                hint(&error, "install the cf cli and add it to your PATH"),
+            )
+        }
+    };
+    let installed = Options {
+        cf_binary_name: cf_binary_name.clone(),
+        ..options.clone()
+    };
+    let environments = settings
+        .environments
+        .iter()
+        .filter(|env| env.get_cf_binary_name(options) == cf_binary_name)
+        .cloned()
+        .collect::<Vec<Environment>>();
+    match check_if_cf_is_installed(settings, &installed, &environments) {
+        Ok(()) => Check::pass(name, format!("cf version {}", version)),
+        Err(error) => Check::fail(
+            name,
+            format!("{:#}", error),
+            hint(&error, "install a newer cf cli"),
+        ),
+    }
+}
+
+/// Only reports the state of the plugin directory, "mcf plugins sync" repairs it
+fn check_plugins(environment: &Environment, original_cf_home: &Path, mcf_folder: &Path) -> Check {
+    let name = format!("{} plugins", environment.name);
+    let directory = plugin_directory(&environment.name, mcf_folder);
+    let state = state(environment, original_cf_home, mcf_folder);
+    let message = format!("{} in {:?}", state, directory);
+    match state {
+        PluginState::NoSharedPlugins => Check::warn(
+            name,
+            state.to_string(),
+            format!(
+                "there are no plugins in {:?}, install plugins with \"cf install-plugin\"",
+                original_cf_home.join("plugins")
+            ),
+        ),
+        PluginState::WrongLink => Check::warn(
+            name,
+            message,
+            String::from("run \"mcf plugins sync\" to link the shared plugins again"),
+        ),
+        PluginState::NotShared => {
+            let error = McfError::PluginsNotShared {
+                env_name: environment.name.clone(),
+                directory,
+            };
+            Check::fail(
+                name,
+                error.to_string(),
+                error
+                    .hint()
+                    .unwrap_or_else(|| String::from("run \"mcf plugins sync\"")),
+            )
+        }
+        PluginState::Shared | PluginState::Isolated | PluginState::Missing => {
+            Check::pass(name, message)
+        }
+    }
+}
+
+/// The hint of the mcf error, or the fallback for other errors
+fn hint(error: &anyhow::Error, fallback: &str) -> String {
+    McfError::find(error)
+        .and_then(McfError::hint)
+        .unwrap_or_else(|| fallback.to_string())
+}
+
+fn check_home(name: String, home: &Path) -> Check {
+    match writable(home) {
+        Ok(()) => Check::pass(name, format!("{:?} is writable", home)),
+        Err(error) => Check::fail(
+            name,
+            format!("{:?} is not writable: {:#}", home, error),
+            format!("make sure you own {:?}, or use --override-path", home),
+        ),
+    }
+}
+
+fn writable(directory: &Path) -> Result<()> {
+    std::fs::create_dir_all(directory)?;
+    let file = directory.join(".mcf-doctor");
+    std::fs::write(&file, "")?;
+    std::fs::remove_file(&file)?;
+    Ok(())
+}
+
+/// A session is valid when cf can get (or refresh) an oauth token with it within the timeout
+async fn check_session(options: &Options, environment: &Environment, mcf_folder: &Path) -> Check {
+    let name = format!("{} session", environment.name);
+    let login_hint = format!("run \"mcf login {}\"", environment.name);
+    if !logged_in(&mcf_folder.join("homes").join(&environment.name)) {
+        return Check::warn(name, String::from("not logged in"), login_hint);
+    }
+    let mut command = cf_command_tokio(options, environment, mcf_folder);
+    command
+        .arg("oauth-token")
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .kill_on_drop(true);
+    match tokio::time::timeout(SESSION_TIMEOUT, command.status()).await {
+        Ok(Ok(status)) if status.success() => Check::pass(name, String::from("logged in")),
+        Ok(_) => Check::warn(name, String::from("session is no longer valid"), login_hint),
+        Err(_) => Check::warn(
+            name,
+            format!(
+                "cf did not get an oauth token within {}s",
+                SESSION_TIMEOUT.as_secs()
+            ),
+            format!(
+                "check that {} is reachable, then {}",
+                environment.url, login_hint
+            ),
+        ),
+    }
+}
+
+/// cf keeps the token of the session in the config.json of its home
+fn logged_in(cf_home: &Path) -> bool {
+    std::fs::read_to_string(cf_home.join(".cf").join("config.json"))
+        .ok()
+        .and_then(|config| serde_json::from_str::<serde_json::Value>(&config).ok())
+        .and_then(|config| {
+            config
+                .get("AccessToken")
+                .and_then(serde_json::Value::as_str)
+                .map(|token| !token.is_empty())
+        })
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_check_settings_fails_on_invalid_settings() {
+        let tempdir: PathBuf = tempdir().unwrap().keep();
+        std::fs::write(tempdir.join("settings.yml"), "environments: [").unwrap();
+        let options = Options {
+            cf_binary_name: String::from("cf"),
+            mcf_home: tempdir.to_string_lossy().to_string(),
+        };
+        let (check, settings) = check_settings(&options);
+        assert_eq!(check.status, Status::Fail);
+        assert!(check.hint.unwrap().contains("mcf env restore"));
+        assert!(settings.is_none());
+    }
+
+    #[test]
+    fn test_check_cf_fails_on_missing_binary() {
+        let check = check_cf(
+            &Settings::default(),
+            &Options {
+                cf_binary_name: String::from("cf"),
+                mcf_home: String::from("/mcf"),
+            },
+            &String::from("mcf-does-not-exist"),
+        );
+        assert_eq!(check.status, Status::Fail);
+        assert_eq!(check.name, "cf cli mcf-does-not-exist");
+        assert!(check.hint.unwrap().contains("--cf-binary-name"));
+    }
+
+    #[test]
+    fn test_check_plugins_warns_without_shared_plugins() {
+        let tempdir: PathBuf = tempdir().unwrap().keep();
+        let environment = Environment {
+            name: String::from("p01"),
+            ..Default::default()
+        };
+        let check = check_plugins(&environment, &tempdir.join(".cf"), &tempdir.join("mcf"));
+        assert_eq!(check.status, Status::Warn);
+        std::fs::create_dir_all(tempdir.join(".cf").join("plugins")).unwrap();
+        let check = check_plugins(&environment, &tempdir.join(".cf"), &tempdir.join("mcf"));
+        assert_eq!(check.status, Status::Pass);
+        assert!(check.message.starts_with("missing"));
+        assert!(!tempdir.join("mcf").exists());
+    }
+
+    #[test]
+    fn test_check_plugins_does_not_remove_own_plugins() {
+        let tempdir: PathBuf = tempdir().unwrap().keep();
+        std::fs::create_dir_all(tempdir.join(".cf").join("plugins")).unwrap();
+        let environment = Environment {
+            name: String::from("p01"),
+            ..Default::default()
+        };
+        let directory = plugin_directory(&environment.name, &tempdir.join("mcf"));
+        std::fs::create_dir_all(&directory).unwrap();
+        std::fs::write(directory.join("own-plugin"), "binary").unwrap();
+        let check = check_plugins(&environment, &tempdir.join(".cf"), &tempdir.join("mcf"));
+        assert_eq!(check.status, Status::Fail);
+        assert!(check.hint.unwrap().contains("mcf plugins sync"));
+        assert!(directory.join("own-plugin").exists());
+    }
+
+    #[test]
+    fn test_check_home() {
+        let tempdir: PathBuf = tempdir().unwrap().keep();
+        let check = check_home(String::from("p01 home"), &tempdir.join("homes").join("p01"));
+        assert_eq!(check.status, Status::Pass);
+        assert_eq!(
+            std::fs::read_dir(tempdir.join("homes").join("p01"))
+                .unwrap()
+                .count(),
+            0
+        );
+        std::fs::write(tempdir.join("file"), "").unwrap();
+        let check = check_home(String::from("file home"), &tempdir.join("file"));
+        assert_eq!(check.status, Status::Fail);
+    }
+
+    #[test]
+    fn test_logged_in() {
+        let tempdir: PathBuf = tempdir().unwrap().keep();
+        assert!(!logged_in(&tempdir));
+        std::fs::create_dir_all(tempdir.join(".cf")).unwrap();
+        std::fs::write(
+            tempdir.join(".cf").join("config.json"),
+            r#"{"AccessToken": ""}"#,
+        )
+        .unwrap();
+        assert!(!logged_in(&tempdir));
+        std::fs::write(
+            tempdir.join(".cf").join("config.json"),
+            r#"{"AccessToken": "bearer eyJ"}"#,
+        )
+        .unwrap();
+        assert!(logged_in(&tempdir));
+    }
+}
@@ -1,7 +1,15 @@
+use crate::cf::Version;
+use crate::error::McfError;
 use crate::options::Options;
 use crate::settings::Settings;
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+const MAX_NAME_LENGTH: usize = 64;
+/// The environment name "all" stands for every environment
+pub const ALL_ENVIRONMENTS: &str = "all";
+const RESERVED_NAMES: [&str; 1] = [ALL_ENVIRONMENTS];
 
 #[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq, Default)]
 pub struct Environment {
@@ -9,11 +17,69 @@ pub struct Environment {
     pub url: String,
     pub sso: bool,
     pub skip_ssl_validation: bool,
+    /// Organization that is targeted on login when no org is given
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub org: Option<String>,
+    /// Space that is targeted on login when no space is given
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub space: Option<String>,
+    /// Binary name for the cloudfoundry cli, used instead of the global cf binary name
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cf_binary: Option<String>,
+    /// Oldest cf cli version that may be used for this environment
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cf_min_version: Option<Version>,
+    /// Process environment variables for every cf command of this environment, values can refer
+    /// to variables of mcf itself with $VAR or ${VAR}
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub env: BTreeMap<String, String>,
+    /// Mutating cf commands on this environment have to be confirmed by typing its name
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub protected: bool,
+    /// This environment has its own plugin directory instead of the shared ~/.cf/plugins
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub isolated_plugins: bool,
 }
 
 impl Environment {
+    /// Environment with every optional field filled in, so all fields show up when serialized
+    fn with_all_fields() -> Environment {
+        Environment {
+            org: Some(String::new()),
+            space: Some(String::new()),
+            cf_binary: Some(String::new()),
+            cf_min_version: Some(Version::new(0, 0, 0)),
+            env: BTreeMap::from([(String::new(), String::new())]),
+            protected: true,
+            isolated_plugins: true,
+            ..Default::default()
+        }
+    }
+
+    /// The process environment variables with $VAR and ${VAR} expanded from the mcf process
+    pub fn get_process_env(&self) -> Vec<(String, String)> {
+        self.env
+            .iter()
+            .map(|(key, value)| (key.clone(), expand_variables(value)))
+            .collect::<Vec<(String, String)>>()
+    }
+
+    /// The process environment variables for display, literal values are masked because they
+    /// can contain credentials (for example a proxy url)
+    pub fn get_masked_env(&self) -> String {
+        self.env
+            .iter()
+            .map(|(key, value)| format!("{}={}", key, mask_value(value)))
+            .collect::<Vec<String>>()
+            .join(", ")
+    }
+
+    pub fn get_cf_binary_name<'a>(&'a self, options: &'a Options) -> &'a String {
+        self.cf_binary.as_ref().unwrap_or(&options.cf_binary_name)
+    }
+
     pub fn get_fields() -> Result<Vec<String>> {
-        Ok(serde_yaml::to_value(Environment::default())?
+        Ok(serde_yaml::to_value(Environment::with_all_fields())?
             .as_mapping()
             .context("could not deserialize environment")?
             .keys()
@@ -23,47 +89,192 @@ impl Environment {
             .collect::<Vec<String>>())
     }
 
+    pub fn validate(&self) -> Result<()> {
+        validate_name(&self.name)?;
+        validate_url(&self.url)
+    }
+
+    /// Settings files can have a url without a scheme from before urls were validated, cf login
+    /// uses https for it as well
+    pub(crate) fn add_missing_url_scheme(&mut self) {
+        if !self.url.is_empty() && !self.url.contains("://") {
+            self.url = format!("https://{}", self.url);
+        }
+    }
+
     pub fn get_values(&self) -> Result<Vec<String>> {
-        Ok(serde_yaml::to_value(self)?
+        let value = serde_yaml::to_value(self)?;
+        let mapping = value
             .as_mapping()
-            .context("could not deserialize environment")?
-            .values()
-            .map(serde_yaml::to_string)
-            .filter(|value| value.is_ok())
-            .map(|values| values.unwrap().to_string().replace('\n', ""))
+            .context("could not deserialize environment")?;
+        Ok(Environment::get_fields()?
+            .iter()
+            .map(|field| match (field.as_str(), mapping.get(field)) {
+                ("env", _) => self.get_masked_env(),
+                (_, Some(value)) => serde_yaml::to_string(value)
+                    .unwrap_or_default()
+                    .replace('\n', ""),
+                (_, None) => String::new(),
+            })
             .collect::<Vec<String>>())
     }
 }
 
-pub fn add(
-    settings: &Settings,
-    options: &Options,
-    name: &String,
-    url: &str,
-    sso: &bool,
-    skip_ssl_validation: &bool,
-) -> Result<()> {
-    let mut environments = settings.environments.clone();
-    environments.retain(|env| &env.name != name);
-    environments.push(Environment {
-        name: name.clone(),
-        url: url.to_owned(),
-        sso: *sso,
-        skip_ssl_validation: *skip_ssl_validation,
-    });
-    let new_settings = Settings {
-        environments,
-    };
-    new_settings.save(options)
+/// Mask a literal value, a reference to a variable does not reveal anything and is kept
+pub fn mask_value(value: &str) -> String {
+    if is_variable_reference(value) {
+        value.to_string()
+    } else {
+        String::from("*****")
+    }
 }
 
-pub fn remove(settings: &Settings, options: &Options, name: &String) -> Result<()> {
-    let mut environments = settings.environments.clone();
-    environments.retain(|env| &env.name != name);
-    let new_settings = Settings {
-        environments,
+fn is_variable_reference(value: &str) -> bool {
+    let name = match value.strip_prefix("${") {
+        Some(some) => some.strip_suffix('}').unwrap_or_default(),
+        None => value.strip_prefix('$').unwrap_or_default(),
     };
-    new_settings.save(options)
+    !name.is_empty() && name.chars().all(is_variable_char)
+}
+
+fn is_variable_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+/// Expand $VAR and ${VAR} from the environment of the mcf process, unknown variables expand to an
+/// empty string like they do in a shell
+pub fn expand_variables(value: &str) -> String {
+    let mut result = String::new();
+    let mut chars = value.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '$' {
+            result.push(c);
+            continue;
+        }
+        let mut name = String::new();
+        if chars.peek() == Some(&'{') {
+            chars.next();
+            for c in chars.by_ref() {
+                if c == '}' {
+                    break;
+                }
+                name.push(c);
+            }
+        } else {
+            while let Some(c) = chars.peek().filter(|c| is_variable_char(**c)) {
+                name.push(*c);
+                chars.next();
+            }
+        }
+        if name.is_empty() {
+            result.push('$');
+        } else {
+            result.push_str(&std::env::var(&name).unwrap_or_default());
+        }
+    }
+    result
+}
+
+/// Environment names end up as a comma separated token on the command line and as a folder name
+/// in mcf home, so only allow characters that are safe in both places.
+pub fn validate_name(name: &str) -> Result<()> {
+    if name.is_empty() {
+        bail!("environment name can not be empty");
+    }
+    if name.len() > MAX_NAME_LENGTH {
+        bail!(
+            "environment name {:?} is longer than {} characters",
+            name,
+            MAX_NAME_LENGTH
+        );
+    }
+    if let Some(invalid) = name
+        .chars()
+        .find(|c| !(c.is_ascii_alphanumeric() || *c == '-' || *c == '_' || *c == '.'))
+    {
+        bail!(
+            "environment name {:?} contains {:?}, only letters, digits, '-', '_' and '.' are allowed",
+            name,
+            invalid
+        );
+    }
+    if name.starts_with('.') {
+        bail!("environment name {:?} can not start with '.'", name);
+    }
+    if RESERVED_NAMES.contains(&name.to_lowercase().as_str()) {
+        bail!(
+            "environment name {:?} is reserved, please choose another name",
+            name
+        );
+    }
+    Ok(())
+}
+
+pub fn validate_url(url: &str) -> Result<()> {
+    let (scheme, rest) = url.split_once("://").with_context(|| {
+        format!(
+            "url {:?} has no scheme, use for example \"https://{}\"",
+            url, url
+        )
+    })?;
+    if scheme != "http" && scheme != "https" {
+        bail!(
+            "url {:?} has scheme {:?}, only \"http\" and \"https\" are supported",
+            url,
+            scheme
+        );
+    }
+    let host = rest.split(['/', '?', '#']).next().unwrap_or_default();
+    if host.is_empty() || host.starts_with(':') {
+        bail!("url {:?} has no host", url);
+    }
+    if url.chars().any(char::is_whitespace) {
+        bail!("url {:?} can not contain whitespace", url);
+    }
+    Ok(())
+}
+
+pub fn add(options: &Options, environment: Environment) -> Result<()> {
+    environment.validate()?;
+    Settings::update(options, |settings| {
+        settings
+            .environments
+            .retain(|env| env.name != environment.name);
+        settings.environments.push(environment);
+        Ok(())
+    })
+}
+
+/// Change the fields of an existing environment, the name of the environment can not be changed
+pub fn update<F>(options: &Options, name: &String, modify: F) -> Result<()>
+where
+    F: FnOnce(&mut Environment),
+{
+    Settings::update(options, |settings| {
+        let index = settings
+            .environments
+            .iter()
+            .position(|env| &env.name == name)
+            .ok_or_else(|| McfError::unknown_environment(name, &settings.environments))?;
+        let environment = &mut settings.environments[index];
+        modify(environment);
+        environment.name = name.clone();
+        environment.validate()
+    })
+}
+
+pub fn remove(options: &Options, name: &String) -> Result<()> {
+    Settings::update(options, |settings| {
+        if !settings.environments.iter().any(|env| &env.name == name) {
+            bail!(McfError::unknown_environment(name, &settings.environments));
+        }
+        settings.environments.retain(|env| &env.name != name);
+        Ok(())
+    })
+}
+
+pub fn restore(options: &Options, backup: &Option<String>) -> Result<String> {
+    Settings::restore(options, backup)
 }
 
 pub fn list(settings: &Settings) -> Vec<Environment> {
@@ -82,37 +293,34 @@ mod tests {
     fn test_add() {
         let env_one = Environment {
             name: "one".to_string(),
-            url: "url_one".to_string(),
+            url: "https://api.one.com".to_string(),
             sso: false,
             skip_ssl_validation: false,
+            ..Default::default()
         };
         let env_two = Environment {
             name: "two".to_string(),
-            url: "url_two".to_string(),
+            url: "https://api.two.com".to_string(),
             sso: true,
             skip_ssl_validation: true,
+            ..Default::default()
         };
         let settings = Settings {
             environments: vec![env_one.clone()],
+            ..Default::default()
         };
-        let tempdir: PathBuf = tempdir().unwrap().into_path();
+        let tempdir: PathBuf = tempdir().unwrap().keep();
         let source = &tempdir.join("environment-test-add");
-        let _ = std::fs::create_dir_all(source);
-        let result = add(
-            &settings,
-            &Options {
-                cf_binary_name: String::from("cf"),
-                mcf_home: source.to_str().unwrap().to_string(),
-            },
-            &env_two.name,
-            &env_two.url,
-            &env_two.sso,
-            &env_two.skip_ssl_validation,
-        );
+        let options = Options {
+            cf_binary_name: String::from("cf"),
+            mcf_home: source.to_str().unwrap().to_string(),
+        };
+        let _ = settings.save(&options);
+        let result = add(&options, env_two);
         assert!(result.is_ok());
         assert_eq!(
             fs::read_to_string(source.join("settings.yml")).unwrap(), 
-            String::from("environments:\n- name: one\n  url: url_one\n  sso: false\n  skip_ssl_validation: false\n- name: two\n  url: url_two\n  sso: true\n  skip_ssl_validation: true\n")
+            String::from("environments:\n- name: one\n  url: https://api.one.com\n  sso: false\n  skip_ssl_validation: false\n- name: two\n  url: https://api.two.com\n  sso: true\n  skip_ssl_validation: true\n")
         );
     }
 
@@ -120,53 +328,245 @@ mod tests {
     fn test_remove() {
         let env_one = Environment {
             name: "one".to_string(),
-            url: "url_one".to_string(),
+            url: "https://api.one.com".to_string(),
             sso: false,
             skip_ssl_validation: false,
+            ..Default::default()
         };
         let env_two = Environment {
             name: "two".to_string(),
-            url: "url_two".to_string(),
+            url: "https://api.two.com".to_string(),
             sso: true,
             skip_ssl_validation: true,
+            ..Default::default()
         };
         let settings = Settings {
             environments: vec![env_one.clone(), env_two.clone()],
+            ..Default::default()
         };
-        let tempdir: PathBuf = tempdir().unwrap().into_path();
+        let tempdir: PathBuf = tempdir().unwrap().keep();
         let source = &tempdir.join("environment-test-add");
-        let _ = std::fs::create_dir_all(source);
-        let result = remove(
-            &settings,
-            &Options {
-                cf_binary_name: String::from("cf"),
-                mcf_home: source.to_str().unwrap().to_string(),
+        let options = Options {
+            cf_binary_name: String::from("cf"),
+            mcf_home: source.to_str().unwrap().to_string(),
+        };
+        let _ = settings.save(&options);
+        let result = remove(&options, &String::from("one"));
+        assert!(result.is_ok());
+        assert_eq!(
+            fs::read_to_string(source.join("settings.yml")).unwrap(), 
+            String::from("environments:\n- name: two\n  url: https://api.two.com\n  sso: true\n  skip_ssl_validation: true\n")
+        );
+    }
+
+    #[test]
+    fn test_remove_unknown_environment() {
+        let tempdir: PathBuf = tempdir().unwrap().keep();
+        let options = Options {
+            cf_binary_name: String::from("cf"),
+            mcf_home: tempdir.to_str().unwrap().to_string(),
+        };
+        Settings {
+            environments: vec![Environment {
+                name: String::from("cf-prod"),
+                url: String::from("https://api.one.com"),
+                ..Default::default()
+            }],
+            ..Default::default()
+        }
+        .save(&options)
+        .unwrap();
+        let result = remove(&options, &String::from("cf-prd"));
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "could not find \"cf-prd\" in environment list, did you mean \"cf-prod\"?"
+        );
+    }
+
+    #[test]
+    fn test_add_rejects_invalid_environment() {
+        let tempdir: PathBuf = tempdir().unwrap().keep();
+        let options = Options {
+            cf_binary_name: String::from("cf"),
+            mcf_home: tempdir.to_str().unwrap().to_string(),
+        };
+        let result = add(
+            &options,
+            Environment {
+                name: String::from("a,b"),
+                url: String::from("https://api.one.com"),
+                ..Default::default()
             },
-            &String::from("one"),
         );
+        assert!(result.is_err());
+        assert!(!tempdir.join("settings.yml").exists());
+    }
+
+    #[test]
+    fn test_update() {
+        let settings = Settings {
+            environments: vec![Environment {
+                name: "one".to_string(),
+                url: "https://api.one.com".to_string(),
+                sso: false,
+                skip_ssl_validation: false,
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let tempdir: PathBuf = tempdir().unwrap().keep();
+        let options = Options {
+            cf_binary_name: String::from("cf"),
+            mcf_home: tempdir.to_str().unwrap().to_string(),
+        };
+        let _ = settings.save(&options);
+        let result = update(&options, &String::from("one"), |env| {
+            env.org = Some(String::from("my-org"));
+            env.space = Some(String::from("my-space"));
+        });
         assert!(result.is_ok());
         assert_eq!(
-            fs::read_to_string(source.join("settings.yml")).unwrap(), 
-            String::from("environments:\n- name: two\n  url: url_two\n  sso: true\n  skip_ssl_validation: true\n")
+            fs::read_to_string(tempdir.join("settings.yml")).unwrap(),
+            String::from("environments:\n- name: one\n  url: https://api.one.com\n  sso: false\n  skip_ssl_validation: false\n  org: my-org\n  space: my-space\n")
+        );
+        assert!(update(&options, &String::from("two"), |_| {}).is_err());
+    }
+
+    #[test]
+    fn test_get_cf_binary_name() {
+        let options = Options {
+            cf_binary_name: String::from("cf"),
+            mcf_home: String::from(""),
+        };
+        let mut env = Environment::default();
+        assert_eq!(env.get_cf_binary_name(&options), "cf");
+        env.cf_binary = Some(String::from("cf7"));
+        assert_eq!(env.get_cf_binary_name(&options), "cf7");
+    }
+
+    #[test]
+    fn test_get_fields_and_values() {
+        let env = Environment {
+            name: "one".to_string(),
+            url: "https://api.one.com".to_string(),
+            sso: true,
+            skip_ssl_validation: false,
+            org: Some("my-org".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            Environment::get_fields().unwrap(),
+            vec![
+                "name",
+                "url",
+                "sso",
+                "skip_ssl_validation",
+                "org",
+                "space",
+                "cf_binary",
+                "cf_min_version",
+                "env",
+                "protected",
+                "isolated_plugins"
+            ]
+        );
+        assert_eq!(
+            env.get_values().unwrap(),
+            vec![
+                "one",
+                "https://api.one.com",
+                "true",
+                "false",
+                "my-org",
+                "",
+                "",
+                "",
+                "",
+                "",
+                ""
+            ]
+        );
+    }
+
+    #[test]
+    fn test_expand_variables() {
+        std::env::set_var("MCF_TEST_EXPAND_VARIABLES", "proxy:8080");
+        assert_eq!(
+            expand_variables("http://$MCF_TEST_EXPAND_VARIABLES"),
+            "http://proxy:8080"
+        );
+        assert_eq!(
+            expand_variables("http://${MCF_TEST_EXPAND_VARIABLES}/path"),
+            "http://proxy:8080/path"
+        );
+        assert_eq!(expand_variables("$MCF_TEST_UNKNOWN_VARIABLE"), "");
+        assert_eq!(expand_variables("costs $ 5"), "costs $ 5");
+    }
+
+    #[test]
+    fn test_get_masked_env() {
+        let env = Environment {
+            env: BTreeMap::from([
+                (String::from("CF_TRACE"), String::from("true")),
+                (String::from("HTTPS_PROXY"), String::from("${PROXY}")),
+                (String::from("SSL_CERT_FILE"), String::from("$CERT_FILE")),
+            ]),
+            ..Default::default()
+        };
+        assert_eq!(
+            env.get_masked_env(),
+            "CF_TRACE=*****, HTTPS_PROXY=${PROXY}, SSL_CERT_FILE=$CERT_FILE"
+        );
+    }
+
+    #[test]
+    fn test_validate_name() {
+        assert!(validate_name("cf-dev_01.eu").is_ok());
+        assert!(validate_name("").is_err());
+        assert!(validate_name("a,b").is_err());
+        assert!(validate_name("../x").is_err());
+        assert!(validate_name("..").is_err());
+        assert!(validate_name("All").is_err());
+        assert!(validate_name(&"a".repeat(MAX_NAME_LENGTH + 1)).is_err());
+        assert_eq!(
+            validate_name("a,b").unwrap_err().to_string(),
+            "environment name \"a,b\" contains ',', only letters, digits, '-', '_' and '.' are allowed"
+        );
+    }
+
+    #[test]
+    fn test_validate_url() {
+        assert!(validate_url("https://api.sys.example.com").is_ok());
+        assert!(validate_url("http://localhost:8080").is_ok());
+        assert_eq!(
+            validate_url("api.sys.example.com").unwrap_err().to_string(),
+            "url \"api.sys.example.com\" has no scheme, use for example \"https://api.sys.example.com\""
         );
+        assert!(validate_url("ftp://api.sys.example.com").is_err());
+        assert!(validate_url("https://").is_err());
+        assert!(validate_url("https://:8080").is_err());
+        assert!(validate_url("https://api example.com").is_err());
     }
 
     #[test]
     fn test_list() {
         let env_one = Environment {
             name: "one".to_string(),
-            url: "url_one".to_string(),
+            url: "https://api.one.com".to_string(),
             sso: false,
             skip_ssl_validation: false,
+            ..Default::default()
         };
         let env_two = Environment {
             name: "two".to_string(),
-            url: "url_two".to_string(),
+            url: "https://api.two.com".to_string(),
             sso: true,
             skip_ssl_validation: true,
+            ..Default::default()
         };
         let settings = Settings {
             environments: vec![env_one.clone(), env_two.clone()],
+            ..Default::default()
         };
         assert_eq!(list(&settings), vec![env_one, env_two]);
     }
@@ -0,0 +1,293 @@
+use std::path::PathBuf;
+use std::process::ExitStatus;
+
+use crate::cf::Version;
+use crate::environment::Environment;
+
+/// The failures of mcf that a tool which embeds mcf can handle. The functions of mcf return
+/// anyhow errors, `McfError::find` gets the mcf error out of them
+///
+/// ```
+/// use lib::error::McfError;
+///
+/// fn describe(error: &anyhow::Error) -> String {
+///     match McfError::find(error) {
+///         Some(McfError::UnknownEnvironment { name, .. }) => format!("{} is unknown", name),
+///         Some(McfError::NonZeroExit { failures }) => format!("{} failed", failures.len()),
+///         _ => error.to_string(),
+///     }
+/// }
+/// ```
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum McfError {
+    /// `suggestion` is the known environment that is so similar that the name may be a typo
+    #[error("could not find {name:?} in environment list{}", suggestion
+        .as_ref()
+        .map(|suggestion| format!(", did you mean {:?}?", suggestion))
+        .unwrap_or_default())]
+    UnknownEnvironment {
+        name: String,
+        known: Vec<String>,
+        suggestion: Option<String>,
+    },
+    /// More than one name is unknown, every name comes with its suggestion
+    #[error("could not find {} in environment list", names
+        .iter()
+        .map(|(name, suggestion)| match suggestion {
+            Some(suggestion) => format!("{:?} (did you mean {:?}?)", name, suggestion),
+            None => format!("{:?}", name),
+        })
+        .collect::<Vec<String>>()
+        .join(", "))]
+    UnknownEnvironments {
+        names: Vec<(String, Option<String>)>,
+        known: Vec<String>,
+    },
+    #[error("could not find cf cli with binary name {binary}")]
+    CfNotInstalled { binary: String },
+    #[error(
+        "cf version {version} of {binary} is not supported, install cf {} or newer",
+        required.major
+    )]
+    CfVersionUnsupported {
+        binary: String,
+        version: Version,
+        required: Version,
+    },
+    /// `required_by` is either "in the settings" or "of environment <name>"
+    #[error("cf version {version} of {binary} is older than cf_min_version {required} {required_by}")]
+    CfVersionTooOld {
+        binary: String,
+        version: Version,
+        required: Version,
+        required_by: String,
+    },
+    /// There are no shared plugins to share with an environment
+    #[error(
+        "can not share the plugins of {env_name}, there are no shared plugins in {directory:?}"
+    )]
+    PluginsMissing {
+        env_name: String,
+        directory: PathBuf,
+    },
+    /// An environment that is not isolated has its own plugins
+    #[error("plugin directory {directory:?} of {env_name} is not shared")]
+    PluginsNotShared {
+        env_name: String,
+        directory: PathBuf,
+    },
+    /// Without a path the merged settings of all settings files could not be parsed
+    #[error("{}", match path {
+        Some(path) => format!("could not parse settings file {:?}", path),
+        None => String::from("could not parse the merged settings of all settings files"),
+    })]
+    SettingsParse {
+        path: Option<PathBuf>,
+        #[source]
+        source: serde_yaml::Error,
+    },
+    #[error("could not spawn {binary} for {env_name}")]
+    SpawnFailed {
+        env_name: String,
+        binary: String,
+        #[source]
+        source: std::io::Error,
+    },
+    /// cf exited with an error on some environments
+    #[error("cf failed on {}", failures
+        .iter()
+        .map(|(env_name, status)| format!("{} ({})", env_name, status))
+        .collect::<Vec<String>>()
+        .join(", "))]
+    NonZeroExit { failures: Vec<(String, ExitStatus)> },
+}
+
+impl McfError {
+    /// The mcf error in the chain of an error, also when context was added to it
+    pub fn find(error: &anyhow::Error) -> Option<&McfError> {
+        error
+            .chain()
+            .find_map(|cause| cause.downcast_ref::<McfError>())
+    }
+
+    /// What the user can do about the error
+    pub fn hint(&self) -> Option<String> {
+        match self {
+            McfError::UnknownEnvironment { known, .. }
+            | McfError::UnknownEnvironments { known, .. }
+                if known.is_empty() =>
+            {
+                Some(String::from(
+                    "there are no environments, add one with \"mcf env add\"",
+                ))
+            }
+            McfError::UnknownEnvironment { known, .. }
+            | McfError::UnknownEnvironments { known, .. } => {
+                Some(format!("the known environments are {}", known.join(", ")))
+            }
+            McfError::CfNotInstalled { binary } => Some(format!(
+                "install the cf cli and add it to your PATH, or change the binary name with \
+                 --cf-binary-name or \"mcf env update <name> --cf-binary {}\"",
+                binary
+            )),
+            McfError::CfVersionUnsupported { .. } | McfError::CfVersionTooOld { .. } => {
+                Some(String::from(
+                    "install a newer cf cli, see https://github.com/cloudfoundry/cli/releases",
+                ))
+            }
+            McfError::PluginsMissing { .. } => Some(String::from(
+                "install plugins with \"cf install-plugin\" first",
+            )),
+            McfError::PluginsNotShared { env_name, .. } => Some(format!(
+                "run \"mcf plugins sync\" to share the plugins again or \"mcf plugins isolate {}\" \
+                 to keep its own plugins",
+                env_name
+            )),
+            McfError::SettingsParse { .. } => Some(String::from(
+                "fix the settings file or run \"mcf env restore\"",
+            )),
+            McfError::SpawnFailed { binary, .. } => Some(format!(
+                "make sure {} is an executable, \"mcf doctor\" checks the cf cli",
+                binary
+            )),
+            McfError::NonZeroExit { .. } => None,
+        }
+    }
+
+    pub(crate) fn unknown_environment(name: &str, environments: &[Environment]) -> McfError {
+        let known = environments
+            .iter()
+            .map(|env| env.name.clone())
+            .collect::<Vec<String>>();
+        McfError::UnknownEnvironment {
+            name: name.to_string(),
+            suggestion: closest_name(name, &known),
+            known,
+        }
+    }
+
+    /// `UnknownEnvironment` for a single name, `UnknownEnvironments` for more names
+    pub(crate) fn unknown_environments(names: &[String], environments: &[Environment]) -> McfError {
+        if let [name] = names {
+            return McfError::unknown_environment(name, environments);
+        }
+        let known = environments
+            .iter()
+            .map(|env| env.name.clone())
+            .collect::<Vec<String>>();
+        McfError::UnknownEnvironments {
+            names: names
+                .iter()
+                .map(|name| (name.clone(), closest_name(name, &known)))
+                .collect(),
+            known,
+        }
+    }
+}
+
+/// The known name with the fewest typos (a missing, extra, wrong or swapped character) compared to
+/// the name, one typo for every three characters at most
+fn closest_name(name: &str, known: &[String]) -> Option<String> {
+    let name = name.to_lowercase();
+    known
+        .iter()
+        .map(|candidate| {
+            (
+                strsim::damerau_levenshtein(&name, &candidate.to_lowercase()),
+                candidate,
+            )
+        })
+        .filter(|(typos, candidate)| *typos <= (candidate.chars().count() / 3).max(1))
+        .min_by_key(|(typos, _)| *typos)
+        .map(|(_, candidate)| candidate.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Context;
+
+    #[test]
+    fn test_find_with_context() {
+        let error = anyhow::Error::from(McfError::CfNotInstalled {
+            binary: String::from("cf8"),
+        })
+        .context("step 1 failed");
+        assert!(matches!(
+            McfError::find(&error),
+            Some(McfError::CfNotInstalled { binary }) if binary == "cf8"
+        ));
+        let result: anyhow::Result<()> = Err(anyhow::anyhow!("other")).context("step 1 failed");
+        assert!(McfError::find(&result.unwrap_err()).is_none());
+    }
+
+    #[test]
+    fn test_hint_of_unknown_environment() {
+        let error = McfError::unknown_environment("p03", &[]);
+        assert_eq!(
+            error.to_string(),
+            "could not find \"p03\" in environment list"
+        );
+        assert!(error.hint().unwrap().contains("mcf env add"));
+        let error = McfError::unknown_environment(
+            "p03",
+            &[Environment {
+                name: String::from("p01"),
+                ..Default::default()
+            }],
+        );
+        assert_eq!(error.hint().unwrap(), "the known environments are p01");
+    }
+
+    #[test]
+    fn test_unknown_environments() {
+        let environments = ["cf-dev", "cf-prod"].map(|name| Environment {
+            name: String::from(name),
+            ..Default::default()
+        });
+        let error = McfError::unknown_environments(
+            &[String::from("cf-prd"), String::from("staging")],
+            &environments,
+        );
+        assert_eq!(
+            error.to_string(),
+            "could not find \"cf-prd\" (did you mean \"cf-prod\"?), \"staging\" in environment list"
+        );
+        assert_eq!(
+            error.hint().unwrap(),
+            "the known environments are cf-dev, cf-prod"
+        );
+        assert!(matches!(
+            McfError::unknown_environments(&[String::from("cf-dve")], &environments),
+            McfError::UnknownEnvironment { suggestion: Some(suggestion), .. } if suggestion == "cf-dev"
+        ));
+    }
+
+    #[test]
+    fn test_suggestion_of_unknown_environment() {
+        let known = ["cf-dev", "cf-prod", "aws-prod"].map(String::from);
+        assert_eq!(
+            closest_name("cf-prd", &known),
+            Some(String::from("cf-prod"))
+        );
+        assert_eq!(closest_name("CF-DEV", &known), Some(String::from("cf-dev")));
+        assert_eq!(closest_name("cf-dve", &known), Some(String::from("cf-dev")));
+        assert_eq!(closest_name("staging", &known), None);
+        assert_eq!(
+            closest_name("p1", &[String::from("p01")]),
+            Some(String::from("p01"))
+        );
+        let error = McfError::unknown_environment(
+            "cf-dve",
+            &[Environment {
+                name: String::from("cf-dev"),
+                ..Default::default()
+            }],
+        );
+        assert_eq!(
+            error.to_string(),
+            "could not find \"cf-dve\" in environment list, did you mean \"cf-dev\"?"
+        );
+    }
+}
@@ -1,15 +1,26 @@
-use std::path::PathBuf;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::process::{ExitStatus, Stdio};
 use std::sync::Arc;
+use std::time::Duration;
 
 use anyhow::{bail, Context, Result};
+use regex::RegexSet;
 use tokio::io::{AsyncBufReadExt, BufReader};
-use tokio::task::JoinSet;
+use tokio::sync::mpsc;
+use tokio::task::{self, JoinSet};
 
-use crate::cf::{child_tokio, CFSubCommandsThatRequireSequentialMode};
-use crate::environment::Environment;
+use crate::alias::expand;
+use crate::cf::{cf_command_tokio, child_tokio, describe_command, prepare_command};
+use crate::environment::{Environment, ALL_ENVIRONMENTS};
+use crate::error::McfError;
+use crate::mcf::{ExecRequest, Mcf};
 use crate::options::Options;
 use crate::settings::Settings;
+use crate::sink::{OutputSink, TerminalSink};
+use crate::subcommand::classify;
 
+/// Execute the command on the environments and print the output to the terminal
 pub async fn exec(
     settings: &Settings,
     options: Arc<Options>,
@@ -19,32 +30,59 @@ pub async fn exec(
     mcf_folder: Arc<PathBuf>,
     sequential_mode: &bool,
 ) -> Result<()> {
-    if CFSubCommandsThatRequireSequentialMode::check_if_contains(command.join(""))
-        || *sequential_mode
-    {
-        exec_sequential(
-            settings,
-            options,
-            names,
-            command,
-            original_cf_home,
-            mcf_folder,
-        )
-        .await
-    } else {
-        exec_parallel(
-            settings,
-            options,
-            names,
-            command,
-            original_cf_home,
-            mcf_folder,
+    Mcf::new(settings.clone(), options.as_ref().clone())
+        .original_cf_home(original_cf_home.as_ref())
+        .mcf_folder(mcf_folder.as_ref())
+        .exec(
+            &ExecRequest::new(names, command.iter().cloned()).sequential(*sequential_mode),
+            &mut TerminalSink::new(),
         )
         .await
+}
+
+/// The command lines that exec would execute, without spawning cf or preparing the plugins
+pub fn exec_dry_run(
+    settings: &Settings,
+    options: &Options,
+    names: &str,
+    command: &[String],
+    mcf_folder: &Path,
+    sequential_mode: &bool,
+) -> Result<Vec<String>> {
+    let command = expand(settings, command)?;
+    let environments = known_environments(names, settings)?;
+    let max_chars = max_environment_name_length(&environments)?;
+    let mut result = vec![format!(
+        "mcf: dry run in {} mode, nothing is executed",
+        if requires_sequential_mode(settings, &command, sequential_mode) {
+            "sequential"
+        } else {
+            "parallel"
+        }
+    )];
+    for environment in environments {
+        let mut cf = cf_command_tokio(options, &environment, mcf_folder);
+        cf.args(&command);
+        result.push(format!(
+            "{}{}",
+            line_prefix(&environment.name, max_chars),
+            describe_command(&cf, &environment)
+        ));
     }
+    Ok(result)
+}
+
+pub(crate) fn requires_sequential_mode(
+    settings: &Settings,
+    command: &[String],
+    sequential_mode: &bool,
+) -> bool {
+    *sequential_mode || classify(settings, command).requires_sequential_mode()
 }
 
-async fn exec_sequential(
+/// Execute the command on all environments at once, the output of every environment is printed
+/// as one block when all environments are done
+pub async fn exec_grouped(
     settings: &Settings,
     options: Arc<Options>,
     names: &str,
@@ -52,78 +90,415 @@ async fn exec_sequential(
     original_cf_home: Arc<PathBuf>,
     mcf_folder: Arc<PathBuf>,
 ) -> Result<()> {
-    let input_environments = input_environments(names, settings);
-    check_if_all_environments_are_known(&input_environments, settings)?;
-    for (_env, env_name) in input_environments {
-        println!(
-            "------------------ NOW ENVIRONMENT {} ------------------",
-            env_name
+    let mcf = Mcf::new(settings.clone(), options.as_ref().clone())
+        .original_cf_home(original_cf_home.as_ref())
+        .mcf_folder(mcf_folder.as_ref());
+    let command = Arc::new(expand(settings, &command)?);
+    let environments = known_environments(names, settings)?;
+    execute(
+        &mcf,
+        environments,
+        command,
+        false,
+        Retries::none(),
+        &mut TerminalSink::grouped(),
+    )
+    .await
+}
+
+/// Execute the command on the environments, one at a time in sequential mode or all at once. In
+/// sequential mode an interactive (or unknown) command uses the terminal itself, the output of
+/// other commands still goes to the sink. When cf could not be executed on an environment the other
+/// environments still are, it fails with that error when all environments are done, or with
+/// `McfError::NonZeroExit` when cf failed on one of the environments
+pub(crate) async fn execute(
+    mcf: &Mcf,
+    environments: Vec<Environment>,
+    command: Arc<Vec<String>>,
+    sequential: bool,
+    mut retries: Retries,
+    sink: &mut dyn OutputSink,
+) -> Result<()> {
+    let classification = classify(mcf.settings(), &command);
+    let terminal = sequential && (classification.interactive || classification.unknown);
+    if terminal {
+        retries.check_terminal()?;
+    }
+    let env_names = environments
+        .iter()
+        .map(|env| env.name.clone())
+        .collect::<Vec<String>>();
+    let original_cf_home = mcf.get_original_cf_home()?;
+    sink.begin(&env_names, sequential);
+    let mut failures = Vec::new();
+    let mut errors = Vec::new();
+    if sequential {
+        'environments: for environment in environments {
+            sink.start(&environment.name);
+            if terminal {
+                sink.terminal(&environment.name);
+            }
+            let mut attempt = 0;
+            let status = loop {
+                let status = if terminal {
+                    match child_tokio(
+                        mcf.get_options(),
+                        command.clone(),
+                        &environment,
+                        original_cf_home.clone(),
+                        mcf.get_mcf_folder(),
+                        &true,
+                    ) {
+                        Ok(mut child) => child.wait().await.map_err(anyhow::Error::from),
+                        Err(error) => Err(error),
+                    }
+                } else {
+                    let (sender, mut receiver) = mpsc::unbounded_channel();
+                    let run = run_environment(
+                        environment.clone(),
+                        mcf.get_options(),
+                        command.clone(),
+                        original_cf_home.clone(),
+                        mcf.get_mcf_folder(),
+                        sender,
+                        attempt,
+                    );
+                    tokio::pin!(run);
+                    let status = loop {
+                        tokio::select! {
+                            Some(output) = receiver.recv() => {
+                                retries.observe(&output);
+                                sink.line(output);
+                            }
+                            status = &mut run => break status,
+                        }
+                    };
+                    while let Ok(output) = receiver.try_recv() {
+                        retries.observe(&output);
+                        sink.line(output);
+                    }
+                    status
+                };
+                let status = match status {
+                    Ok(status) => status,
+                    Err(error) => {
+                        sink.error(&environment.name, &error);
+                        errors.push((environment.name.clone(), error));
+                        continue 'environments;
+                    }
+                };
+                let Some(next) = retries.next_attempt(&environment.name, status) else {
+                    break status;
+                };
+                attempt = next;
+                sink.retry(&environment.name, attempt, status, retries.delay(attempt));
+                tokio::time::sleep(retries.delay(attempt)).await;
+            };
+            if !status.success() {
+                failures.push((environment.name.clone(), status));
+            }
+            sink.exit(&environment.name, status);
+        }
+    } else {
+        env_names.iter().for_each(|env_name| sink.start(env_name));
+        let by_name = environments
+            .iter()
+            .map(|env| (env.name.clone(), env.clone()))
+            .collect::<HashMap<String, Environment>>();
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        let mut tasks = spawn_parallel(
+            environments,
+            mcf.get_options(),
+            command.clone(),
+            original_cf_home.clone(),
+            mcf.get_mcf_folder(),
+            sender.clone(),
         );
-        let options = options.clone();
-        let command = command.clone();
-        let original_cf_home = original_cf_home.clone();
-        let mcf_folder = mcf_folder.clone();
-        let child: tokio::process::Child = child_tokio(
-            options,
-            command,
-            &env_name,
-            original_cf_home,
-            mcf_folder,
-            &true,
-        )?;
-        child.wait_with_output().await?;
+        while !tasks.is_empty() {
+            tokio::select! {
+                biased;
+                Some(output) = receiver.recv() => {
+                    retries.observe(&output);
+                    sink.line(output);
+                }
+                Some((env_name, result)) = tasks.join_next() => {
+                    // the lines of an environment are sent before its task is done, so they are
+                    // all received before its exit status
+                    while let Ok(output) = receiver.try_recv() {
+                        retries.observe(&output);
+                        sink.line(output);
+                    }
+                    let status = match result {
+                        Ok(status) => status,
+                        Err(error) => {
+                            sink.error(&env_name, &error);
+                            errors.push((env_name, error));
+                            continue;
+                        }
+                    };
+                    if let Some(attempt) = retries.next_attempt(&env_name, status) {
+                        let delay = retries.delay(attempt);
+                        sink.retry(&env_name, attempt, status, delay);
+                        let task = run_environment(
+                            by_name[&env_name].clone(),
+                            mcf.get_options(),
+                            command.clone(),
+                            original_cf_home.clone(),
+                            mcf.get_mcf_folder(),
+                            sender.clone(),
+                            attempt,
+                        );
+                        tasks.spawn(env_name, async move {
+                            tokio::time::sleep(delay).await;
+                            task.await
+                        });
+                        continue;
+                    }
+                    if !status.success() {
+                        failures.push((env_name.clone(), status));
+                    }
+                    sink.exit(&env_name, status);
+                }
+            }
+        }
+    }
+    sink.end();
+    check_outcome(failures, errors)
+}
+
+/// Fails with the error of the first environment on which cf could not be executed, or with
+/// `McfError::NonZeroExit` when cf failed on some of the environments
+pub(crate) fn check_outcome(
+    failures: Vec<(String, ExitStatus)>,
+    errors: Vec<(String, anyhow::Error)>,
+) -> Result<()> {
+    let env_names = errors
+        .iter()
+        .map(|(env_name, _)| env_name.clone())
+        .chain(failures.iter().map(|(env_name, _)| env_name.clone()))
+        .collect::<Vec<String>>();
+    if let Some((_, error)) = errors.into_iter().next() {
+        return Err(error.context(format!("mcf: cf failed on {}", env_names.join(", "))));
+    }
+    if !failures.is_empty() {
+        bail!(McfError::NonZeroExit { failures });
     }
     Ok(())
 }
 
-async fn exec_parallel(
-    settings: &Settings,
+/// When cf is executed again for an environment on which it exited with an error
+#[derive(Debug, Clone)]
+pub(crate) struct Retries {
+    retries: u32,
+    delay: Duration,
+    /// Only output that matches one of the patterns is a reason to retry, without patterns every
+    /// failure is
+    patterns: RegexSet,
+    attempts: HashMap<String, u32>,
+    transient: HashSet<String>,
+}
+
+impl Retries {
+    pub(crate) fn new(retries: u32, delay: Duration, patterns: &[String]) -> Result<Retries> {
+        Ok(Retries {
+            retries,
+            delay,
+            patterns: RegexSet::new(patterns).context("invalid retry_patterns in the settings")?,
+            attempts: HashMap::new(),
+            transient: HashSet::new(),
+        })
+    }
+
+    pub(crate) fn none() -> Retries {
+        Retries {
+            retries: 0,
+            delay: Duration::ZERO,
+            patterns: RegexSet::empty(),
+            attempts: HashMap::new(),
+            transient: HashSet::new(),
+        }
+    }
+
+    /// When cf uses the terminal its output can not be matched against the patterns. Retrying
+    /// every failure instead could execute a command that is not idempotent again, so retries with
+    /// patterns are refused
+    fn check_terminal(&self) -> Result<()> {
+        if self.retries > 0 && !self.patterns.is_empty() {
+            bail!(
+                "mcf: can not retry an interactive command with retry_patterns in the settings, cf \
+                 uses the terminal so its output can not be matched against the patterns"
+            );
+        }
+        Ok(())
+    }
+
+    fn observe(&mut self, output: &OutputLine) {
+        if self.patterns.is_match(&output.line) {
+            self.transient.insert(output.env_name.clone());
+        }
+    }
+
+    /// The number of the next attempt, none when cf succeeded, when there are no retries left or
+    /// when the output of the last attempt did not match the patterns
+    fn next_attempt(&mut self, env_name: &str, status: ExitStatus) -> Option<u32> {
+        let transient = self.transient.remove(env_name) || self.patterns.is_empty();
+        let attempt = self.attempts.entry(env_name.to_string()).or_default();
+        if status.success() || *attempt >= self.retries || !transient {
+            return None;
+        }
+        *attempt += 1;
+        Some(*attempt)
+    }
+
+    /// The wait before an attempt, it doubles with every retry
+    fn delay(&self, attempt: u32) -> Duration {
+        self.delay
+            .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+    }
+}
+
+/// A line of the output of cf for an environment
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutputLine {
+    pub env_name: String,
+    pub line: String,
+    pub stderr: bool,
+    /// 0 for the first execution of cf, the number of the retry otherwise
+    pub attempt: u32,
+}
+
+/// The tasks that execute cf for the environments, every task is known by its environment, also
+/// when it panicked
+#[derive(Debug, Default)]
+pub(crate) struct EnvironmentTasks {
+    tasks: JoinSet<Result<ExitStatus>>,
+    env_names: HashMap<task::Id, String>,
+}
+
+impl EnvironmentTasks {
+    pub(crate) fn spawn<F>(&mut self, env_name: String, task: F)
+    where
+        F: std::future::Future<Output = Result<ExitStatus>> + Send + 'static,
+    {
+        let id = self.tasks.spawn(task).id();
+        self.env_names.insert(id, env_name);
+    }
+
+    /// The environment and the exit status of cf of the next task that is done
+    pub(crate) async fn join_next(&mut self) -> Option<(String, Result<ExitStatus>)> {
+        let (id, result) = match self.tasks.join_next_with_id().await? {
+            Ok((id, result)) => (id, result),
+            Err(error) => (error.id(), Err(error.into())),
+        };
+        Some((self.env_names.remove(&id).unwrap_or_default(), result))
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.tasks.is_empty()
+    }
+
+    pub(crate) fn abort_all(&mut self) {
+        self.tasks.abort_all();
+    }
+}
+
+/// Spawn cf for every environment at once and send every line of its stdout and stderr, the
+/// channel closes when all environments are done and every task returns the exit status of cf
+pub(crate) fn spawn_parallel(
+    environments: Vec<Environment>,
     options: Arc<Options>,
-    names: &str,
     command: Arc<Vec<String>>,
     original_cf_home: Arc<PathBuf>,
     mcf_folder: Arc<PathBuf>,
-) -> Result<()> {
-    let input_environments = input_environments(names, settings);
-    check_if_all_environments_are_known(&input_environments, settings)?;
-    let mut tasks: JoinSet<Result<()>> = JoinSet::new();
-    let max_chars = max_environment_name_length(&input_environments)?;
-    for (_env, env_name) in input_environments {
-        let options = options.clone();
-        let command = command.clone();
-        let original_cf_home = original_cf_home.clone();
-        let mcf_folder = mcf_folder.clone();
-        tasks.spawn(async move {
-            let whitespace_length = max_chars - env_name.len();
-            let whitespace = (0..=whitespace_length).map(|_| " ").collect::<String>();
-            let child: tokio::process::Child = child_tokio(
-                options,
-                command,
-                &env_name,
-                original_cf_home,
-                mcf_folder,
-                &false,
-            )?;
-            let stdout = child.stdout.context("exec: no stdout")?;
-            let mut lines = BufReader::new(stdout).lines();
-            while let Some(line) = lines.next_line().await? {
-                println!("{}{}| {}", &env_name, whitespace, line);
-            }
-            Ok(())
-        });
+    sender: mpsc::UnboundedSender<OutputLine>,
+) -> EnvironmentTasks {
+    let mut tasks = EnvironmentTasks::default();
+    for environment in environments {
+        tasks.spawn(
+            environment.name.clone(),
+            run_environment(
+                environment,
+                options.clone(),
+                command.clone(),
+                original_cf_home.clone(),
+                mcf_folder.clone(),
+                sender.clone(),
+                0,
+            ),
+        );
     }
-    while let Some(result) = tasks.join_next().await {
-        result??;
+    tasks
+}
+
+/// Execute cf for an environment and send every line of its stdout and stderr
+async fn run_environment(
+    environment: Environment,
+    options: Arc<Options>,
+    command: Arc<Vec<String>>,
+    original_cf_home: Arc<PathBuf>,
+    mcf_folder: Arc<PathBuf>,
+    sender: mpsc::UnboundedSender<OutputLine>,
+    attempt: u32,
+) -> Result<ExitStatus> {
+    let mut child = prepare_command(
+        &options,
+        &command,
+        &environment,
+        &original_cf_home,
+        &mcf_folder,
+    )?
+    .stdout(Stdio::piped())
+    .stderr(Stdio::piped())
+    .spawn()
+    .map_err(|source| McfError::SpawnFailed {
+        env_name: environment.name.clone(),
+        binary: environment.get_cf_binary_name(&options).clone(),
+        source,
+    })?;
+    let mut stdout = BufReader::new(child.stdout.take().context("exec: no stdout")?).lines();
+    let mut stderr = BufReader::new(child.stderr.take().context("exec: no stderr")?).lines();
+    let (mut stdout_open, mut stderr_open) = (true, true);
+    while stdout_open || stderr_open {
+        let (line, is_stderr) = tokio::select! {
+            line = stdout.next_line(), if stdout_open => (line?, false),
+            line = stderr.next_line(), if stderr_open => (line?, true),
+        };
+        let Some(line) = line else {
+            if is_stderr {
+                stderr_open = false;
+            } else {
+                stdout_open = false;
+            }
+            continue;
+        };
+        let output = OutputLine {
+            env_name: environment.name.clone(),
+            line,
+            stderr: is_stderr,
+            attempt,
+        };
+        // the receiver is gone when nobody is interested in the output anymore
+        if sender.send(output).is_err() {
+            child.kill().await?;
+            break;
+        }
     }
-    Ok(())
+    Ok(child.wait().await?)
 }
 
-fn max_environment_name_length(
-    input_environments: &[(Option<Environment>, String)],
+/// The environment name padded to the longest environment name, followed by "| "
+pub(crate) fn line_prefix(env_name: &str, max_chars: usize) -> String {
+    let whitespace_length = max_chars - env_name.len();
+    let whitespace = (0..=whitespace_length).map(|_| " ").collect::<String>();
+    format!("{}{}| ", env_name, whitespace)
+}
+
+pub(crate) fn max_environment_name_length(
+    environments: &[Environment],
 ) -> Result<usize, anyhow::Error> {
-    let result = input_environments
+    let result = environments
         .iter()
-        .map(|(_env, env_name)| env_name.len())
+        .map(|env| env.name.len())
         .max()
         .context("environment name should have length")?;
     Ok(result)
@@ -133,19 +508,39 @@ fn check_if_all_environments_are_known(
     input_environments: &[(Option<Environment>, String)],
     settings: &Settings,
 ) -> Result<()> {
-    for env in input_environments.iter() {
-        if env.0.is_none() {
-            bail!(
-                "could not find {:#?} in environment list {:#?}",
-                env.1,
-                settings.environments
-            );
-        }
+    let unknown = input_environments
+        .iter()
+        .filter(|(env, _)| env.is_none())
+        .map(|(_, env_name)| env_name.clone())
+        .collect::<Vec<String>>();
+    if !unknown.is_empty() {
+        bail!(McfError::unknown_environments(
+            &unknown,
+            &settings.environments
+        ));
     }
     Ok(())
 }
 
+/// The environments for the comma separated names ("all" for every environment), fails with all
+/// the names that are unknown
+pub fn known_environments(names: &str, settings: &Settings) -> Result<Vec<Environment>> {
+    let input_environments = input_environments(names, settings);
+    check_if_all_environments_are_known(&input_environments, settings)?;
+    Ok(input_environments
+        .into_iter()
+        .filter_map(|(env, _env_name)| env)
+        .collect::<Vec<Environment>>())
+}
+
 fn input_environments(names: &str, settings: &Settings) -> Vec<(Option<Environment>, String)> {
+    if names.eq_ignore_ascii_case(ALL_ENVIRONMENTS) && !settings.environments.is_empty() {
+        return settings
+            .environments
+            .iter()
+            .map(|env| (Some(env.clone()), env.name.clone()))
+            .collect();
+    }
     names
         .split(',')
         .map(|s| s.to_string())
@@ -162,9 +557,50 @@ mod tests {
 
     use super::*;
 
+    #[test]
+    fn test_known_environments_all() {
+        let settings = Settings {
+            environments: ["p01", "p02"]
+                .iter()
+                .map(|name| Environment {
+                    name: name.to_string(),
+                    url: String::from("url"),
+                    ..Default::default()
+                })
+                .collect(),
+            ..Default::default()
+        };
+        let names = |names: &str| {
+            known_environments(names, &settings)
+                .unwrap()
+                .into_iter()
+                .map(|env| env.name)
+                .collect::<Vec<String>>()
+        };
+        assert_eq!(names("all"), vec!["p01", "p02"]);
+        assert_eq!(names("ALL"), vec!["p01", "p02"]);
+        assert_eq!(names("p02"), vec!["p02"]);
+        assert!(known_environments("all", &Settings::default()).is_err());
+        assert_eq!(
+            known_environments("p1,p02,staging", &settings)
+                .unwrap_err()
+                .to_string(),
+            "could not find \"p1\" (did you mean \"p01\"?), \"staging\" in environment list"
+        );
+    }
+
+    #[test]
+    fn test_retry_delay_doubles() {
+        let retries = Retries::new(3, Duration::from_secs(5), &[]).unwrap();
+        assert_eq!(retries.delay(1), Duration::from_secs(5));
+        assert_eq!(retries.delay(2), Duration::from_secs(10));
+        assert_eq!(retries.delay(3), Duration::from_secs(20));
+        assert!(Retries::new(1, Duration::ZERO, &[String::from("(")]).is_err());
+    }
+
     #[tokio::test]
     async fn test_exec_could_not_find_env_in_list() {
-        let tempdir: PathBuf = tempdir().unwrap().into_path();
+        let tempdir: PathBuf = tempdir().unwrap().keep();
         let result = exec(
             &Settings {
                 environments: vec![Environment {
@@ -172,7 +608,9 @@ mod tests {
                     url: "url".to_string(),
                     sso: false,
                     skip_ssl_validation: false,
+                    ..Default::default()
                 }],
+                ..Default::default()
             },
             Arc::new(Options {
                 cf_binary_name: String::from("echo"),
@@ -186,15 +624,20 @@ mod tests {
         )
         .await;
         assert!(result.is_err());
+        let error = result.unwrap_err();
         assert_eq!(
-            result.unwrap_err().to_string(),
-            "could not find \"p02\" in environment list [\n    Environment {\n        name: \"p01\",\n        url: \"url\",\n        sso: false,\n        skip_ssl_validation: false,\n    },\n]"
+            error.to_string(),
+            "could not find \"p02\" in environment list, did you mean \"p01\"?"
         );
+        assert!(matches!(
+            McfError::find(&error),
+            Some(McfError::UnknownEnvironment { name, .. }) if name == "p02"
+        ));
     }
 
     #[tokio::test]
     async fn test_exec_environment_should_have_length() {
-        let tempdir: PathBuf = tempdir().unwrap().into_path();
+        let tempdir: PathBuf = tempdir().unwrap().keep();
         let _ = std::fs::create_dir_all(tempdir.join(".cf").join("plugins"));
         let result = exec(
             &Settings {
@@ -203,7 +646,9 @@ mod tests {
                     url: "url".to_string(),
                     sso: false,
                     skip_ssl_validation: false,
+                    ..Default::default()
                 }],
+                ..Default::default()
             },
             Arc::new(Options {
                 cf_binary_name: String::from("echo"),
@@ -219,16 +664,68 @@ mod tests {
         assert!(result.is_ok());
     }
 
+    #[test]
+    fn test_exec_dry_run() {
+        let tempdir: PathBuf = tempdir().unwrap().keep();
+        let mcf_folder = tempdir.join("test-exec-dry-run");
+        let result = exec_dry_run(
+            &Settings {
+                environments: vec![
+                    Environment {
+                        name: "p01".to_string(),
+                        url: "url".to_string(),
+                        ..Default::default()
+                    },
+                    Environment {
+                        name: "dev1".to_string(),
+                        url: "url".to_string(),
+                        cf_binary: Some(String::from("cf7")),
+                        ..Default::default()
+                    },
+                ],
+                ..Default::default()
+            },
+            &Options {
+                cf_binary_name: String::from("cf"),
+                mcf_home: tempdir.to_str().unwrap().to_string(),
+            },
+            "p01,dev1",
+            &[String::from("delete"), String::from("my-app")],
+            &mcf_folder,
+            &false,
+        )
+        .unwrap();
+        assert_eq!(
+            result,
+            vec![
+                String::from("mcf: dry run in sequential mode, nothing is executed"),
+                format!(
+                    "p01  | CF_HOME={} cf delete my-app",
+                    mcf_folder.join("homes").join("p01").to_str().unwrap()
+                ),
+                format!(
+                    "dev1 | CF_HOME={} cf7 delete my-app",
+                    mcf_folder.join("homes").join("dev1").to_str().unwrap()
+                ),
+            ]
+        );
+        assert!(!mcf_folder.exists());
+    }
+
     #[tokio::test]
     async fn test_exec() {
+        let _stdout = crate::STDOUT_REDIRECT.lock().await;
         test_if_run_in_sequential_mode_when_boolean_is_true().await;
         test_if_run_in_sequential_mode_when_boolean_is_false_but_command_is_in_enum_list().await;
         test_if_run_in_parallel_mode().await;
+        test_if_alias_is_expanded().await;
+        test_if_non_interactive_ssh_runs_in_parallel_mode().await;
+        test_if_output_is_grouped_per_environment().await;
     }
 
     async fn test_if_run_in_sequential_mode_when_boolean_is_true() {
         let mut buf = BufferRedirect::stdout().unwrap();
-        let tempdir: PathBuf = tempdir().unwrap().into_path();
+        let tempdir: PathBuf = tempdir().unwrap().keep();
         let _ = std::fs::create_dir_all(tempdir.join(".cf").join("plugins"));
         let result = exec(
             &Settings {
@@ -237,7 +734,9 @@ mod tests {
                     url: "url".to_string(),
                     sso: false,
                     skip_ssl_validation: false,
+                    ..Default::default()
                 }],
+                ..Default::default()
             },
             Arc::new(Options {
                 cf_binary_name: String::from("echo"),
@@ -260,7 +759,7 @@ mod tests {
 
     async fn test_if_run_in_sequential_mode_when_boolean_is_false_but_command_is_in_enum_list() {
         let mut buf = BufferRedirect::stdout().unwrap();
-        let tempdir: PathBuf = tempdir().unwrap().into_path();
+        let tempdir: PathBuf = tempdir().unwrap().keep();
         let _ = std::fs::create_dir_all(tempdir.join(".cf").join("plugins"));
         let result = exec(
             &Settings {
@@ -269,7 +768,9 @@ mod tests {
                     url: "url".to_string(),
                     sso: false,
                     skip_ssl_validation: false,
+                    ..Default::default()
                 }],
+                ..Default::default()
             },
             Arc::new(Options {
                 cf_binary_name: String::from("echo"),
@@ -292,7 +793,7 @@ mod tests {
 
     async fn test_if_run_in_parallel_mode() {
         let mut buf = BufferRedirect::stdout().unwrap();
-        let tempdir: PathBuf = tempdir().unwrap().into_path();
+        let tempdir: PathBuf = tempdir().unwrap().keep();
         let _ = std::fs::create_dir_all(tempdir.join(".cf").join("plugins"));
         let result = exec(
             &Settings {
@@ -301,7 +802,10 @@ mod tests {
                     url: "url".to_string(),
                     sso: false,
                     skip_ssl_validation: false,
+                    ..Default::default()
                 }],
+                subcommands: [(String::from("hello"), Default::default())].into(),
+                ..Default::default()
             },
             Arc::new(Options {
                 cf_binary_name: String::from("echo"),
@@ -321,4 +825,117 @@ mod tests {
         assert!(!output.contains("------------------ NOW ENVIRONMENT p01 ------------------\n"));
         assert!(output.contains("p01 | Hello\n"));
     }
+
+    async fn test_if_alias_is_expanded() {
+        let mut buf = BufferRedirect::stdout().unwrap();
+        let tempdir: PathBuf = tempdir().unwrap().keep();
+        let _ = std::fs::create_dir_all(tempdir.join(".cf").join("plugins"));
+        let result = exec(
+            &Settings {
+                environments: vec![Environment {
+                    name: "p01".to_string(),
+                    url: "url".to_string(),
+                    sso: false,
+                    skip_ssl_validation: false,
+                    ..Default::default()
+                }],
+                aliases: std::collections::BTreeMap::from([(
+                    String::from("greet"),
+                    vec![String::from("Hello"), String::from("$1")],
+                )]),
+                subcommands: [(String::from("hello"), Default::default())].into(),
+                ..Default::default()
+            },
+            Arc::new(Options {
+                cf_binary_name: String::from("echo"),
+                mcf_home: tempdir.to_str().unwrap().to_string(),
+            }),
+            &String::from("p01"),
+            Arc::new(vec![String::from("greet"), String::from("World")]),
+            Arc::new(tempdir.join(".cf")),
+            Arc::new(tempdir.join("test-exec-environment-should-have-length")),
+            &false,
+        )
+        .await;
+        assert!(result.is_ok());
+        let mut output = String::new();
+        buf.read_to_string(&mut output).unwrap();
+        drop(buf);
+        assert!(output.contains("p01 | Hello World\n"));
+    }
+
+    async fn test_if_output_is_grouped_per_environment() {
+        let mut buf = BufferRedirect::stdout().unwrap();
+        let tempdir: PathBuf = tempdir().unwrap().keep();
+        let _ = std::fs::create_dir_all(tempdir.join(".cf").join("plugins"));
+        let result = exec_grouped(
+            &Settings {
+                environments: vec![
+                    Environment {
+                        name: "p01".to_string(),
+                        url: "url".to_string(),
+                        ..Default::default()
+                    },
+                    Environment {
+                        name: "p02".to_string(),
+                        url: "url".to_string(),
+                        ..Default::default()
+                    },
+                ],
+                ..Default::default()
+            },
+            Arc::new(Options {
+                cf_binary_name: String::from("echo"),
+                mcf_home: tempdir.to_str().unwrap().to_string(),
+            }),
+            &String::from("p02,p01"),
+            Arc::new(vec![String::from("Hello")]),
+            Arc::new(tempdir.join(".cf")),
+            Arc::new(tempdir.join("test-exec-grouped")),
+        )
+        .await;
+        assert!(result.is_ok());
+        let mut output = String::new();
+        buf.read_to_string(&mut output).unwrap();
+        drop(buf);
+        assert!(output.contains(
+            "------------------ ENVIRONMENT p02 ------------------\nHello\n------------------ ENVIRONMENT p01 ------------------\nHello\n"
+        ));
+    }
+
+    async fn test_if_non_interactive_ssh_runs_in_parallel_mode() {
+        let mut buf = BufferRedirect::stdout().unwrap();
+        let tempdir: PathBuf = tempdir().unwrap().keep();
+        let _ = std::fs::create_dir_all(tempdir.join(".cf").join("plugins"));
+        let result = exec(
+            &Settings {
+                environments: vec![Environment {
+                    name: "p01".to_string(),
+                    url: "url".to_string(),
+                    ..Default::default()
+                }],
+                ..Default::default()
+            },
+            Arc::new(Options {
+                cf_binary_name: String::from("echo"),
+                mcf_home: tempdir.to_str().unwrap().to_string(),
+            }),
+            &String::from("p01"),
+            Arc::new(vec![
+                String::from("ssh"),
+                String::from("my-app"),
+                String::from("-c"),
+                String::from("ls"),
+            ]),
+            Arc::new(tempdir.join(".cf")),
+            Arc::new(tempdir.join("test-exec-environment-should-have-length")),
+            &false,
+        )
+        .await;
+        assert!(result.is_ok());
+        let mut output = String::new();
+        buf.read_to_string(&mut output).unwrap();
+        drop(buf);
+        assert!(output.contains("p01 | ssh my-app -c ls\n"));
+    }
 }
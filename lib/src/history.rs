@@ -0,0 +1,514 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitStatus;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+pub use jiff::Timestamp;
+use jiff::{tz::TimeZone, ToSpan};
+use serde::{Deserialize, Serialize};
+
+use crate::exec::OutputLine;
+use crate::mcf::ExecRequest;
+use crate::options::Options;
+use crate::sink::{retry_label, OutputSink};
+
+const HISTORY_DIRECTORY: &str = "history";
+const RUN_FILE: &str = "run.yml";
+const DEFAULT_MAX_RUNS: usize = 100;
+
+/// How many runs of "mcf exec" the history keeps
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone)]
+pub struct HistorySettings {
+    /// The newest runs that are kept, 0 disables the history
+    #[serde(default = "default_max_runs")]
+    pub max_runs: usize,
+    /// Older runs are removed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_age_days: Option<u32>,
+}
+
+fn default_max_runs() -> usize {
+    DEFAULT_MAX_RUNS
+}
+
+impl Default for HistorySettings {
+    fn default() -> Self {
+        HistorySettings {
+            max_runs: DEFAULT_MAX_RUNS,
+            max_age_days: None,
+        }
+    }
+}
+
+impl HistorySettings {
+    pub fn is_default(&self) -> bool {
+        self == &HistorySettings::default()
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.max_runs > 0
+    }
+}
+
+/// A recorded run of "mcf exec", the output of every environment is next to it in <environment>.log
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone)]
+pub struct Run {
+    pub id: String,
+    pub started: Timestamp,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub finished: Option<Timestamp>,
+    /// The environment names as they were given
+    pub names: String,
+    /// The environments the command was executed on
+    pub environments: Vec<String>,
+    pub command: Vec<String>,
+    pub sequential: bool,
+    /// Whether the output of cf is in the log files, not when cf used the terminal (an interactive
+    /// command in sequential mode). Runs that were recorded before this field only recorded the
+    /// output in parallel mode
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output_recorded: Option<bool>,
+    /// The exit code of every environment that is done, none when cf was killed by a signal
+    #[serde(default)]
+    pub exit_codes: BTreeMap<String, Option<i32>>,
+}
+
+impl Run {
+    pub fn output_recorded(&self) -> bool {
+        self.output_recorded.unwrap_or(!self.sequential)
+    }
+
+    /// The environments on which cf did not succeed, including the ones that did not finish
+    pub fn failed_environments(&self) -> Vec<String> {
+        self.environments
+            .iter()
+            .filter(|env| self.exit_codes.get(*env) != Some(&Some(0)))
+            .cloned()
+            .collect()
+    }
+}
+
+/// A timestamp in the time zone of the system, "2022-09-02 15:53:16"
+pub fn local_time(timestamp: &Timestamp) -> String {
+    timestamp
+        .to_zoned(TimeZone::system())
+        .strftime("%Y-%m-%d %H:%M:%S")
+        .to_string()
+}
+
+pub fn history_folder(options: &Options) -> PathBuf {
+    options.get_mcf_home_path_buf().join(HISTORY_DIRECTORY)
+}
+
+/// All recorded runs, the oldest first
+pub fn list(options: &Options) -> Result<Vec<Run>> {
+    let folder = history_folder(options);
+    if !folder.exists() {
+        return Ok(Vec::new());
+    }
+    let mut runs = fs::read_dir(&folder)
+        .with_context(|| format!("could not read the history {:?}", folder))?
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| read_run(&entry.path()).ok())
+        .collect::<Vec<Run>>();
+    runs.sort_by(|a, b| a.started.cmp(&b.started).then_with(|| a.id.cmp(&b.id)));
+    Ok(runs)
+}
+
+/// A recorded run, "last" is the newest run
+pub fn get(options: &Options, id: &str) -> Result<Run> {
+    if id == "last" {
+        return list(options)?.pop().context("the history is empty");
+    }
+    if id.is_empty() || id.starts_with('.') || id.contains(['/', '\\']) {
+        bail!("{:?} is not a run id", id);
+    }
+    read_run(&history_folder(options).join(id)).with_context(|| {
+        format!(
+            "could not find run {:?} in the history, see \"mcf history list\"",
+            id
+        )
+    })
+}
+
+/// The output of cf for an environment in a run, empty when cf used the terminal (see
+/// `Run::output_recorded`)
+pub fn output(options: &Options, run: &Run, env_name: &str) -> Result<String> {
+    let path = history_folder(options)
+        .join(&run.id)
+        .join(format!("{}.log", env_name));
+    if !path.exists() {
+        return Ok(String::new());
+    }
+    fs::read_to_string(&path).with_context(|| format!("could not read {:?}", path))
+}
+
+/// The request that executes the command of the last run again on the environments on which it
+/// failed or did not finish, none when nothing failed. Fails when the history is disabled, the last
+/// run in it is not the last run anymore then
+pub fn failed_from_last(
+    options: &Options,
+    settings: &HistorySettings,
+) -> Result<Option<(Run, ExecRequest)>> {
+    if !settings.enabled() {
+        bail!("the history is disabled (history.max_runs is 0), so mcf does not know the last run");
+    }
+    let run = get(options, "last")
+        .context("there is no last run to retry, \"mcf exec\" records its runs in the history")?;
+    let failed = run.failed_environments();
+    if failed.is_empty() {
+        return Ok(None);
+    }
+    let request =
+        ExecRequest::new(failed.join(","), run.command.iter().cloned()).sequential(run.sequential);
+    Ok(Some((run, request)))
+}
+
+/// Remove the runs that are too many or too old
+pub fn prune(options: &Options, settings: &HistorySettings) -> Result<()> {
+    let runs = list(options)?;
+    let too_many = runs.len().saturating_sub(settings.max_runs);
+    let oldest = settings.max_age_days.and_then(|days| {
+        Timestamp::now()
+            .checked_sub((i64::from(days) * 24).hours())
+            .ok()
+    });
+    for (index, run) in runs.iter().enumerate() {
+        if index < too_many || oldest.is_some_and(|oldest| run.started < oldest) {
+            let folder = history_folder(options).join(&run.id);
+            fs::remove_dir_all(&folder)
+                .with_context(|| format!("could not remove {:?}", folder))?;
+        }
+    }
+    Ok(())
+}
+
+fn read_run(folder: &Path) -> Result<Run> {
+    let path = folder.join(RUN_FILE);
+    Ok(serde_yaml::from_str(&fs::read_to_string(&path)?)?)
+}
+
+/// Records a run in the history and passes everything on to the inner sink. Recording never
+/// makes the run fail, the first problem is kept in `error`
+pub struct RecordingSink<'a> {
+    inner: &'a mut dyn OutputSink,
+    options: Options,
+    settings: HistorySettings,
+    run: Run,
+    folder: Option<PathBuf>,
+    logs: BTreeMap<String, File>,
+    error: Option<anyhow::Error>,
+}
+
+impl<'a> RecordingSink<'a> {
+    pub fn new(
+        options: &Options,
+        settings: &HistorySettings,
+        request: &ExecRequest,
+        inner: &'a mut dyn OutputSink,
+    ) -> RecordingSink<'a> {
+        RecordingSink {
+            inner,
+            options: options.clone(),
+            settings: settings.clone(),
+            run: Run {
+                id: String::new(),
+                started: Timestamp::now(),
+                finished: None,
+                names: request.names().to_string(),
+                environments: Vec::new(),
+                command: request.command().to_vec(),
+                sequential: false,
+                output_recorded: None,
+                exit_codes: BTreeMap::new(),
+            },
+            folder: None,
+            logs: BTreeMap::new(),
+            error: None,
+        }
+    }
+
+    /// The run as it is recorded so far
+    pub fn run(&self) -> &Run {
+        &self.run
+    }
+
+    /// The first problem with recording the run
+    pub fn error(&self) -> Option<&anyhow::Error> {
+        self.error.as_ref()
+    }
+
+    fn record<F>(&mut self, record: F)
+    where
+        F: FnOnce(&mut RecordingSink<'a>) -> Result<()>,
+    {
+        if self.error.is_some() {
+            return;
+        }
+        if let Err(error) = record(self) {
+            self.error = Some(error);
+        }
+    }
+
+    /// Create the folder of the run, named after its start time
+    fn create_folder(&mut self) -> Result<()> {
+        let history = history_folder(&self.options);
+        fs::create_dir_all(&history)
+            .with_context(|| format!("could not create the history {:?}", history))?;
+        let base = self
+            .run
+            .started
+            .to_zoned(TimeZone::system())
+            .strftime("%Y-%m-%d-%H%M%S")
+            .to_string();
+        for number in 1.. {
+            let id = if number == 1 {
+                base.clone()
+            } else {
+                format!("{}-{}", base, number)
+            };
+            match fs::create_dir(history.join(&id)) {
+                Ok(()) => {
+                    self.folder = Some(history.join(&id));
+                    self.run.id = id;
+                    return Ok(());
+                }
+                // another mcf started a run in the same second
+                Err(error) if error.kind() == ErrorKind::AlreadyExists => continue,
+                Err(error) => {
+                    return Err(error).with_context(|| format!("could not create {:?}", id))
+                }
+            }
+        }
+        unreachable!()
+    }
+
+    fn save(&self) -> Result<()> {
+        let folder = self.folder.as_ref().context("the run has no folder")?;
+        fs::write(folder.join(RUN_FILE), serde_yaml::to_string(&self.run)?)?;
+        Ok(())
+    }
+}
+
+impl OutputSink for RecordingSink<'_> {
+    fn begin(&mut self, env_names: &[String], sequential: bool) {
+        self.run.environments = env_names.to_vec();
+        self.run.sequential = sequential;
+        self.run.output_recorded = Some(true);
+        self.record(|sink| {
+            sink.create_folder()?;
+            sink.save()?;
+            let folder = sink.folder.clone().context("the run has no folder")?;
+            for env_name in env_names {
+                let log = File::create(folder.join(format!("{}.log", env_name)))?;
+                sink.logs.insert(env_name.clone(), log);
+            }
+            Ok(())
+        });
+        self.inner.begin(env_names, sequential);
+    }
+
+    fn start(&mut self, env_name: &str) {
+        self.inner.start(env_name);
+    }
+
+    fn terminal(&mut self, env_name: &str) {
+        self.run.output_recorded = Some(false);
+        self.record(|sink| sink.save());
+        self.inner.terminal(env_name);
+    }
+
+    fn line(&mut self, line: OutputLine) {
+        self.record(|sink| {
+            if let Some(log) = sink.logs.get_mut(&line.env_name) {
+                writeln!(log, "{}{}", retry_label(line.attempt), line.line)?;
+            }
+            Ok(())
+        });
+        self.inner.line(line);
+    }
+
+    fn retry(&mut self, env_name: &str, attempt: u32, status: ExitStatus, delay: Duration) {
+        self.inner.retry(env_name, attempt, status, delay);
+    }
+
+    fn exit(&mut self, env_name: &str, status: ExitStatus) {
+        self.run
+            .exit_codes
+            .insert(env_name.to_string(), status.code());
+        self.record(|sink| sink.save());
+        self.inner.exit(env_name, status);
+    }
+
+    fn error(&mut self, env_name: &str, error: &anyhow::Error) {
+        self.inner.error(env_name, error);
+    }
+
+    fn end(&mut self) {
+        self.run.finished = Some(Timestamp::now());
+        self.record(|sink| {
+            sink.save()?;
+            prune(&sink.options, &sink.settings)
+        });
+        self.inner.end();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::environment::Environment;
+    use crate::mcf::Mcf;
+    use crate::settings::Settings;
+    use crate::sink::CollectingSink;
+    use crate::subcommand::SubcommandClasses;
+    use tempfile::tempdir;
+
+    fn options(tempdir: &Path) -> Options {
+        Options {
+            cf_binary_name: String::from("echo"),
+            mcf_home: tempdir.to_string_lossy().to_string(),
+        }
+    }
+
+    async fn exec(tempdir: &Path, request: &ExecRequest, settings: &HistorySettings) -> Run {
+        std::fs::create_dir_all(tempdir.join(".cf").join("plugins")).unwrap();
+        let mcf = Mcf::new(
+            Settings {
+                environments: ["p01", "p02"]
+                    .iter()
+                    .map(|name| Environment {
+                        name: name.to_string(),
+                        url: String::from("url"),
+                        ..Default::default()
+                    })
+                    .collect(),
+                subcommands: [(String::from("hello"), SubcommandClasses::default())].into(),
+                ..Default::default()
+            },
+            options(tempdir),
+        )
+        .original_cf_home(tempdir.join(".cf"));
+        let mut inner = CollectingSink::new();
+        let mut sink = RecordingSink::new(&options(tempdir), settings, request, &mut inner);
+        mcf.exec(request, &mut sink).await.unwrap();
+        assert!(sink.error().is_none());
+        let run = sink.run().clone();
+        assert_eq!(inner.output("p01"), vec!["Hello"]);
+        run
+    }
+
+    #[tokio::test]
+    async fn test_record_and_read_run() {
+        let tempdir: PathBuf = tempdir().unwrap().keep();
+        let request = ExecRequest::new("p02,p01", ["Hello"]);
+        let run = exec(&tempdir, &request, &HistorySettings::default()).await;
+        assert_eq!(run.environments, vec!["p02", "p01"]);
+        assert_eq!(run.exit_codes.get("p01"), Some(&Some(0)));
+        assert!(run.finished.is_some());
+        assert!(run.failed_environments().is_empty());
+        let options = options(&tempdir);
+        assert_eq!(get(&options, &run.id).unwrap(), run);
+        assert_eq!(get(&options, "last").unwrap(), run);
+        assert_eq!(output(&options, &run, "p02").unwrap(), "Hello\n");
+        assert!(get(&options, "../settings").is_err());
+        assert!(get(&options, "2001-01-01-000000").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_prune_keeps_max_runs() {
+        let tempdir: PathBuf = tempdir().unwrap().keep();
+        let settings = HistorySettings {
+            max_runs: 2,
+            max_age_days: None,
+        };
+        let request = ExecRequest::new("p01", ["Hello"]);
+        let mut ids = Vec::new();
+        for _ in 0..3 {
+            ids.push(exec(&tempdir, &request, &settings).await.id);
+        }
+        let runs = list(&options(&tempdir)).unwrap();
+        assert_eq!(
+            runs.iter().map(|run| &run.id).collect::<Vec<&String>>(),
+            vec![&ids[1], &ids[2]]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_failed_from_last() {
+        let tempdir: PathBuf = tempdir().unwrap().keep();
+        let options = options(&tempdir);
+        let settings = HistorySettings::default();
+        assert!(failed_from_last(&options, &settings).is_err());
+        exec(
+            &tempdir,
+            &ExecRequest::new("p01", ["Hello"]),
+            &HistorySettings::default(),
+        )
+        .await;
+        assert!(failed_from_last(&options, &settings).unwrap().is_none());
+        // a run that was interrupted before p02 was done
+        let folder = history_folder(&options).join("9999-01-01-000000");
+        std::fs::create_dir_all(&folder).unwrap();
+        std::fs::write(
+            folder.join(RUN_FILE),
+            "id: 9999-01-01-000000\nstarted: 9999-01-01T00:00:00Z\nnames: all\n\
+             environments: [p01, p02]\ncommand: [push, app]\nsequential: true\n\
+             exit_codes: {p01: 0}\n",
+        )
+        .unwrap();
+        let (run, request) = failed_from_last(&options, &settings).unwrap().unwrap();
+        assert_eq!(run.id, "9999-01-01-000000");
+        assert_eq!(
+            request,
+            ExecRequest::new("p02", ["push", "app"]).sequential(true)
+        );
+        let disabled = HistorySettings {
+            max_runs: 0,
+            max_age_days: None,
+        };
+        assert!(failed_from_last(&options, &disabled)
+            .unwrap_err()
+            .to_string()
+            .starts_with("the history is disabled"));
+    }
+
+    #[tokio::test]
+    async fn test_record_output_in_sequential_mode() {
+        let tempdir: PathBuf = tempdir().unwrap().keep();
+        let options = options(&tempdir);
+        let run = exec(
+            &tempdir,
+            &ExecRequest::new("p01,p02", ["Hello"]).sequential(true),
+            &HistorySettings::default(),
+        )
+        .await;
+        assert!(run.sequential && run.output_recorded());
+        assert_eq!(output(&options, &run, "p02").unwrap(), "Hello\n");
+        // runs that were recorded before output_recorded existed
+        let old = Run {
+            output_recorded: None,
+            ..run
+        };
+        assert!(!old.output_recorded());
+    }
+
+    #[test]
+    fn test_failed_environments() {
+        let run = Run {
+            id: String::from("2022-09-02-155316"),
+            started: Timestamp::now(),
+            finished: None,
+            names: String::from("p01,p02,p03"),
+            environments: ["p01", "p02", "p03"].map(String::from).to_vec(),
+            command: vec![String::from("apps")],
+            sequential: false,
+            output_recorded: Some(true),
+            exit_codes: [(String::from("p01"), Some(0)), (String::from("p02"), None)].into(),
+        };
+        assert_eq!(run.failed_environments(), vec!["p02", "p03"]);
+    }
+}
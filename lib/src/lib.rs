@@ -1,6 +1,25 @@
 #![crate_type = "lib"]
+pub mod alias;
 pub mod cf;
+mod cf_cache;
+pub mod config;
+pub mod doctor;
 pub mod environment;
+pub mod error;
 pub mod exec;
+pub mod history;
+pub mod logs;
+pub mod mcf;
 pub mod options;
+pub mod plugins;
+pub mod protection;
+pub mod runbook;
 pub mod settings;
+pub mod shell;
+pub mod sink;
+pub mod subcommand;
+pub mod tui;
+
+/// Tests that redirect stdout have to take this lock, only one redirect can exist at a time
+#[cfg(test)]
+pub(crate) static STDOUT_REDIRECT: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
@@ -0,0 +1,500 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Result};
+use jiff::{civil::DateTime, fmt::strtime, tz::TimeZone, Span, Timestamp, Zoned};
+use tokio::sync::mpsc;
+
+use crate::exec::{check_outcome, known_environments, spawn_parallel, OutputLine};
+use crate::mcf::Mcf;
+use crate::sink::OutputSink;
+
+/// How long a log line is held back, so that the lines of a foundation that buffers can still go
+/// in front of it
+pub const DEFAULT_REORDER_WINDOW: Duration = Duration::from_secs(1);
+
+/// How often the lines whose window is over are handed to the sink
+const TICK: Duration = Duration::from_millis(100);
+
+/// The format of the timestamp of loggregator (example "2022-09-02T15:53:16.16+0200")
+const TIMESTAMP_FORMAT: &str = "%Y-%m-%dT%H:%M:%S%.f%z";
+
+/// A line of "cf logs" (example "2022-09-02T15:53:16.16+0200 [APP/PROC/WEB/0] OUT started")
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogLine {
+    pub timestamp: Timestamp,
+    /// The source without the instance index (example "RTR", "APP/PROC/WEB" or "CELL")
+    pub source_type: String,
+    pub instance: Option<u32>,
+    /// "OUT" or "ERR"
+    pub stream: String,
+    pub message: String,
+}
+
+impl LogLine {
+    /// The log line, none for other output of cf (example "Retrieving logs for app ...") and for
+    /// the continuation lines of a message
+    pub fn parse(line: &str) -> Option<LogLine> {
+        let (timestamp, rest) = line.trim_start().split_once(' ')?;
+        let timestamp = strtime::parse(TIMESTAMP_FORMAT, timestamp)
+            .and_then(|time| time.to_timestamp())
+            .ok()?;
+        let (source, rest) = rest.strip_prefix('[')?.split_once(']')?;
+        let (source_type, instance) = match source.rsplit_once('/') {
+            Some((source_type, index)) if index.parse::<u32>().is_ok() => {
+                (source_type, index.parse().ok())
+            }
+            _ => (source, None),
+        };
+        let rest = rest.trim_start();
+        let (stream, message) = rest.split_once(' ').unwrap_or((rest, ""));
+        Some(LogLine {
+            timestamp,
+            source_type: source_type.to_string(),
+            instance,
+            stream: stream.to_string(),
+            message: message.to_string(),
+        })
+    }
+}
+
+/// Which log lines are shown, an empty list allows everything
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LogFilter {
+    /// Source types (example "RTR"), "APP" also allows "APP/PROC/WEB", case insensitive
+    pub source_types: Vec<String>,
+    pub instances: Vec<u32>,
+    pub since: Option<Timestamp>,
+    pub until: Option<Timestamp>,
+}
+
+impl LogFilter {
+    pub fn matches(&self, log_line: &LogLine) -> bool {
+        let source_type = log_line.source_type.to_uppercase();
+        (self.source_types.is_empty()
+            || self.source_types.iter().any(|allowed| {
+                let allowed = allowed.to_uppercase();
+                source_type == allowed || source_type.starts_with(&format!("{}/", allowed))
+            }))
+            && (self.instances.is_empty()
+                || log_line
+                    .instance
+                    .is_some_and(|instance| self.instances.contains(&instance)))
+            && self.since.is_none_or(|since| log_line.timestamp >= since)
+            && self.until.is_none_or(|until| log_line.timestamp <= until)
+    }
+}
+
+/// A point in time for the filter: a timestamp (example "2022-09-02T15:53:16+02:00" or the format
+/// of the log lines), a local date and time (example "2022-09-02 15:53") or a time span before now
+/// (example "10m" or "2h 30m")
+pub fn parse_time(value: &str) -> Result<Timestamp> {
+    if let Ok(timestamp) = value.parse::<Timestamp>() {
+        return Ok(timestamp);
+    }
+    if let Ok(timestamp) = strtime::parse(TIMESTAMP_FORMAT, value).and_then(|t| t.to_timestamp()) {
+        return Ok(timestamp);
+    }
+    if let Ok(date_time) = value.parse::<DateTime>() {
+        return Ok(date_time.to_zoned(TimeZone::system())?.timestamp());
+    }
+    if let Ok(span) = value.parse::<Span>() {
+        return Ok(Zoned::now().checked_sub(span)?.timestamp());
+    }
+    bail!(
+        "expected a timestamp like 2022-09-02T15:53:16+02:00, a local time like \"2022-09-02 15:53\" \
+         or a time span like 10m but got {:?}",
+        value
+    )
+}
+
+/// The logs of an app on environments
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogsRequest {
+    names: String,
+    app: String,
+    recent: bool,
+    filter: LogFilter,
+    window: Duration,
+}
+
+impl LogsRequest {
+    /// The logs of the app for the comma separated environment names (example "cf-dev,cf-prod")
+    pub fn new(names: impl Into<String>, app: impl Into<String>) -> LogsRequest {
+        LogsRequest {
+            names: names.into(),
+            app: app.into(),
+            recent: false,
+            filter: LogFilter::default(),
+            window: DEFAULT_REORDER_WINDOW,
+        }
+    }
+
+    /// The recent logs instead of following the logs, they are sorted when all are received
+    pub fn recent(mut self, recent: bool) -> LogsRequest {
+        self.recent = recent;
+        self
+    }
+
+    pub fn filter(mut self, filter: LogFilter) -> LogsRequest {
+        self.filter = filter;
+        self
+    }
+
+    /// How long a log line is held back while following, a longer window puts more lines of a
+    /// foundation that buffers in order but shows every line later
+    pub fn window(mut self, window: Duration) -> LogsRequest {
+        self.window = window;
+        self
+    }
+
+    pub fn names(&self) -> &str {
+        &self.names
+    }
+
+    /// The cf command that gets the logs
+    pub fn command(&self) -> Vec<String> {
+        let mut command = vec![String::from("logs"), self.app.clone()];
+        if self.recent {
+            command.push(String::from("--recent"));
+        }
+        command
+    }
+}
+
+/// Execute "cf logs" on the environments and send the log lines of all environments to the sink in
+/// timestamp order. Other output of cf is sent right away, when all environments are done it fails
+/// like exec when cf could not be executed or failed on some of the environments
+pub(crate) async fn follow(
+    mcf: &Mcf,
+    request: &LogsRequest,
+    sink: &mut dyn OutputSink,
+) -> Result<()> {
+    let environments = known_environments(&request.names, mcf.settings())?;
+    let env_names = environments
+        .iter()
+        .map(|env| env.name.clone())
+        .collect::<Vec<String>>();
+    let original_cf_home = mcf.get_original_cf_home()?;
+    sink.begin(&env_names, false);
+    env_names.iter().for_each(|env_name| sink.start(env_name));
+    let (sender, mut receiver) = mpsc::unbounded_channel();
+    let mut tasks = spawn_parallel(
+        environments,
+        mcf.get_options(),
+        Arc::new(request.command()),
+        original_cf_home,
+        mcf.get_mcf_folder(),
+        sender,
+    );
+    let mut merger = Merger::new((!request.recent).then_some(request.window));
+    let mut ticks = tokio::time::interval(TICK);
+    let mut exits = Vec::new();
+    while !tasks.is_empty() || !receiver.is_closed() || !receiver.is_empty() {
+        // not biased, a steady stream of lines would starve the ticks and hold lines back
+        tokio::select! {
+            Some(output) = receiver.recv() => {
+                let now = Instant::now();
+                if let Some(output) = merger.push(output, &request.filter, now) {
+                    sink.line(output);
+                }
+                merger.ready(now).into_iter().for_each(|output| sink.line(output));
+            }
+            Some((env_name, result)) = tasks.join_next() => exits.push((env_name, result)),
+            _ = ticks.tick() => merger.ready(Instant::now()).into_iter().for_each(|output| sink.line(output)),
+        }
+    }
+    merger
+        .drain()
+        .into_iter()
+        .for_each(|output| sink.line(output));
+    let mut failures = Vec::new();
+    let mut errors = Vec::new();
+    for (env_name, result) in exits {
+        match result {
+            Ok(status) => {
+                if !status.success() {
+                    failures.push((env_name.clone(), status));
+                }
+                sink.exit(&env_name, status);
+            }
+            Err(error) => {
+                sink.error(&env_name, &error);
+                errors.push((env_name, error));
+            }
+        }
+    }
+    sink.end();
+    check_outcome(failures, errors)
+}
+
+/// The lines of a log message, a message can continue on the following lines (example a stack
+/// trace)
+#[derive(Debug)]
+struct Entry {
+    lines: Vec<OutputLine>,
+    arrived: Instant,
+}
+
+/// The last log line of an environment, its continuation lines belong to it
+#[derive(Debug, Clone, Copy)]
+enum Last {
+    Kept((Timestamp, u64)),
+    Dropped,
+}
+
+/// Puts the log lines of all environments in timestamp order, every line is held back until the
+/// window after its arrival is over. Without a window the lines are held back until they are
+/// drained
+#[derive(Debug)]
+struct Merger {
+    window: Option<Duration>,
+    entries: BTreeMap<(Timestamp, u64), Entry>,
+    last: HashMap<String, Last>,
+    sequence: u64,
+}
+
+impl Merger {
+    fn new(window: Option<Duration>) -> Merger {
+        Merger {
+            window,
+            entries: BTreeMap::new(),
+            last: HashMap::new(),
+            sequence: 0,
+        }
+    }
+
+    /// Hold the line back when it is a log line or belongs to one, other output is returned to be
+    /// shown right away
+    fn push(&mut self, output: OutputLine, filter: &LogFilter, now: Instant) -> Option<OutputLine> {
+        let Some(log_line) = LogLine::parse(&output.line) else {
+            return match self.last.get(&output.env_name) {
+                Some(Last::Dropped) => None,
+                Some(Last::Kept(key)) => match self.entries.get_mut(key) {
+                    Some(entry) => {
+                        entry.lines.push(output);
+                        None
+                    }
+                    // the log line is shown already, so is its continuation
+                    None => Some(output),
+                },
+                None => Some(output),
+            };
+        };
+        if !filter.matches(&log_line) {
+            self.last.insert(output.env_name, Last::Dropped);
+            return None;
+        }
+        let key = (log_line.timestamp, self.sequence);
+        self.sequence += 1;
+        self.last.insert(output.env_name.clone(), Last::Kept(key));
+        self.entries.insert(
+            key,
+            Entry {
+                lines: vec![output],
+                arrived: now,
+            },
+        );
+        None
+    }
+
+    /// The oldest log lines whose window is over, in timestamp order
+    fn ready(&mut self, now: Instant) -> Vec<OutputLine> {
+        let Some(window) = self.window else {
+            return Vec::new();
+        };
+        let mut result = Vec::new();
+        while let Some(entry) = self.entries.first_entry() {
+            if now.saturating_duration_since(entry.get().arrived) < window {
+                break;
+            }
+            result.extend(entry.remove().lines);
+        }
+        result
+    }
+
+    /// All log lines that are held back, in timestamp order
+    fn drain(&mut self) -> Vec<OutputLine> {
+        std::mem::take(&mut self.entries)
+            .into_values()
+            .flat_map(|entry| entry.lines)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn output(env_name: &str, line: &str) -> OutputLine {
+        OutputLine {
+            env_name: env_name.to_string(),
+            line: line.to_string(),
+            stderr: false,
+            attempt: 0,
+        }
+    }
+
+    fn lines(outputs: Vec<OutputLine>) -> Vec<String> {
+        outputs
+            .into_iter()
+            .map(|output| format!("{} {}", output.env_name, output.line.trim_start()))
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_log_line() {
+        let log_line =
+            LogLine::parse("   2022-09-02T15:53:16.16+0200 [RTR/1] OUT GET /health 200").unwrap();
+        assert_eq!(
+            log_line.timestamp,
+            "2022-09-02T13:53:16.16Z".parse::<Timestamp>().unwrap()
+        );
+        assert_eq!(log_line.source_type, "RTR");
+        assert_eq!(log_line.instance, Some(1));
+        assert_eq!(log_line.stream, "OUT");
+        assert_eq!(log_line.message, "GET /health 200");
+        let log_line =
+            LogLine::parse("2022-09-02T15:53:17.00-0700 [APP/PROC/WEB/0] ERR boom").unwrap();
+        assert_eq!(log_line.source_type, "APP/PROC/WEB");
+        assert_eq!(log_line.instance, Some(0));
+        assert_eq!(log_line.stream, "ERR");
+        let log_line = LogLine::parse("2022-09-02T15:53:17.00+0000 [API] OUT").unwrap();
+        assert_eq!(log_line.source_type, "API");
+        assert_eq!(log_line.instance, None);
+        assert_eq!(log_line.message, "");
+        assert!(LogLine::parse("Retrieving logs for app test-service as user...").is_none());
+        assert!(LogLine::parse("").is_none());
+        assert!(LogLine::parse("    at com.example.Main(Main.java:12)").is_none());
+    }
+
+    #[test]
+    fn test_filter() {
+        let rtr = LogLine::parse("2022-09-02T15:53:16.16+0200 [RTR/1] OUT GET /").unwrap();
+        let app = LogLine::parse("2022-09-02T15:53:18.16+0200 [APP/PROC/WEB/0] OUT hi").unwrap();
+        assert!(LogFilter::default().matches(&rtr));
+        let filter = LogFilter {
+            source_types: vec![String::from("app")],
+            ..Default::default()
+        };
+        assert!(!filter.matches(&rtr));
+        assert!(filter.matches(&app));
+        let filter = LogFilter {
+            instances: vec![1],
+            ..Default::default()
+        };
+        assert!(filter.matches(&rtr));
+        assert!(!filter.matches(&app));
+        let filter = LogFilter {
+            since: Some(parse_time("2022-09-02T15:53:17+02:00").unwrap()),
+            until: Some(parse_time("2022-09-02T15:53:18.16+0200").unwrap()),
+            ..Default::default()
+        };
+        assert!(!filter.matches(&rtr));
+        assert!(filter.matches(&app));
+    }
+
+    #[test]
+    fn test_parse_time() {
+        assert!(parse_time("2022-09-02 15:53").is_ok());
+        let ten_minutes_ago = parse_time("10m").unwrap();
+        let difference = Timestamp::now().duration_since(ten_minutes_ago);
+        assert!(difference.as_secs() >= 600 && difference.as_secs() < 660);
+        assert!(parse_time("yesterday-ish").is_err());
+    }
+
+    #[test]
+    fn test_merge_in_timestamp_order() {
+        let start = Instant::now();
+        let mut merger = Merger::new(Some(Duration::from_secs(1)));
+        let filter = LogFilter::default();
+        let header = merger.push(output("p01", "Retrieving logs..."), &filter, start);
+        assert_eq!(header.unwrap().line, "Retrieving logs...");
+        for (env_name, line) in [
+            ("p01", "   2022-09-02T15:53:16.16+0200 [RTR/1] OUT line 1"),
+            ("p01", "   2022-09-02T15:53:18.16+0200 [RTR/2] OUT line 3"),
+            ("p02", "   2022-09-02T15:53:17.16+0200 [RTR/3] OUT line 2"),
+            ("p02", "    at continuation of line 2"),
+        ] {
+            assert!(merger
+                .push(output(env_name, line), &filter, start)
+                .is_none());
+        }
+        assert!(merger.ready(start).is_empty());
+        assert_eq!(
+            lines(merger.ready(start + Duration::from_secs(1))),
+            vec![
+                "p01 2022-09-02T15:53:16.16+0200 [RTR/1] OUT line 1",
+                "p02 2022-09-02T15:53:17.16+0200 [RTR/3] OUT line 2",
+                "p02 at continuation of line 2",
+                "p01 2022-09-02T15:53:18.16+0200 [RTR/2] OUT line 3",
+            ]
+        );
+        // too late for the window, so it can only be shown after the newer lines
+        let late = Instant::now() + Duration::from_secs(2);
+        let line = "2022-09-02T15:53:15.16+0200 [RTR/1] OUT line 0";
+        assert!(merger.push(output("p02", line), &filter, late).is_none());
+        assert_eq!(merger.drain().len(), 1);
+    }
+
+    #[test]
+    fn test_merge_holds_back_the_newer_lines() {
+        let start = Instant::now();
+        let mut merger = Merger::new(Some(Duration::from_secs(1)));
+        let filter = LogFilter::default();
+        let line = "2022-09-02T15:53:18.16+0200 [RTR/1] OUT newer";
+        merger.push(output("p01", line), &filter, start);
+        let line = "2022-09-02T15:53:17.16+0200 [RTR/1] OUT older";
+        merger.push(
+            output("p02", line),
+            &filter,
+            start + Duration::from_millis(500),
+        );
+        // the older line of p02 is in front, so the newer line waits for it
+        assert!(merger.ready(start + Duration::from_secs(1)).is_empty());
+        assert_eq!(
+            lines(merger.ready(start + Duration::from_millis(1500))),
+            vec![
+                "p02 2022-09-02T15:53:17.16+0200 [RTR/1] OUT older",
+                "p01 2022-09-02T15:53:18.16+0200 [RTR/1] OUT newer",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_merge_without_window_and_with_filter() {
+        let start = Instant::now();
+        let mut merger = Merger::new(None);
+        let filter = LogFilter {
+            source_types: vec![String::from("APP")],
+            ..Default::default()
+        };
+        for (env_name, line) in [
+            (
+                "p01",
+                "2022-09-02T15:53:18.16+0200 [APP/PROC/WEB/0] ERR Exception",
+            ),
+            ("p01", "    at Main.java:12"),
+            ("p02", "2022-09-02T15:53:16.16+0200 [RTR/1] OUT GET /"),
+            ("p02", "    continuation of a dropped line"),
+            (
+                "p02",
+                "2022-09-02T15:53:17.16+0200 [APP/PROC/WEB/1] OUT started",
+            ),
+        ] {
+            assert!(merger
+                .push(output(env_name, line), &filter, start)
+                .is_none());
+        }
+        assert!(merger.ready(start + Duration::from_secs(60)).is_empty());
+        assert_eq!(
+            lines(merger.drain()),
+            vec![
+                "p02 2022-09-02T15:53:17.16+0200 [APP/PROC/WEB/1] OUT started",
+                "p01 2022-09-02T15:53:18.16+0200 [APP/PROC/WEB/0] ERR Exception",
+                "p01 at Main.java:12",
+            ]
+        );
+    }
+}
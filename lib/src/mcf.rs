@@ -0,0 +1,465 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+
+use crate::alias::expand;
+use crate::exec::{exec_dry_run, execute, known_environments, requires_sequential_mode, Retries};
+use crate::logs::{follow, LogsRequest};
+use crate::options::{default_cf_home, Options};
+use crate::settings::Settings;
+use crate::sink::OutputSink;
+
+/// Executes cf commands on multiple environments, this is the api for tools that embed mcf
+///
+/// ```no_run
+/// # async fn example() -> anyhow::Result<()> {
+/// use lib::mcf::{ExecRequest, Mcf};
+/// use lib::options::Options;
+/// use lib::sink::CollectingSink;
+///
+/// let mcf = Mcf::load(Options::new(None, None)?)?;
+/// let mut sink = CollectingSink::new();
+/// mcf.exec(&ExecRequest::new("cf-dev,cf-prod", ["apps"]), &mut sink)
+///     .await?;
+/// for line in sink.output("cf-dev") {
+///     println!("{}", line);
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct Mcf {
+    settings: Arc<Settings>,
+    options: Arc<Options>,
+    original_cf_home: Option<Arc<PathBuf>>,
+    mcf_folder: Option<Arc<PathBuf>>,
+}
+
+impl Mcf {
+    /// mcf with the given settings and options and the cf home of the current user
+    pub fn new(settings: Settings, options: Options) -> Mcf {
+        Mcf {
+            settings: Arc::new(settings),
+            options: Arc::new(options),
+            original_cf_home: default_cf_home().map(Arc::new),
+            mcf_folder: None,
+        }
+    }
+
+    /// mcf with the settings from the settings files that belong to the options
+    pub fn load(options: Options) -> Result<Mcf> {
+        Ok(Mcf::new(Settings::load(&options)?, options))
+    }
+
+    /// The .cf folder with the plugins that are shared by the environments
+    pub fn original_cf_home(mut self, original_cf_home: impl Into<PathBuf>) -> Mcf {
+        self.original_cf_home = Some(Arc::new(original_cf_home.into()));
+        self
+    }
+
+    /// The folder with the cf homes of the environments, the mcf home of the options by default
+    pub fn mcf_folder(mut self, mcf_folder: impl Into<PathBuf>) -> Mcf {
+        self.mcf_folder = Some(Arc::new(mcf_folder.into()));
+        self
+    }
+
+    pub fn settings(&self) -> &Settings {
+        &self.settings
+    }
+
+    pub(crate) fn get_options(&self) -> Arc<Options> {
+        self.options.clone()
+    }
+
+    /// Fails when there is no home directory and no original cf home was given
+    pub(crate) fn get_original_cf_home(&self) -> Result<Arc<PathBuf>> {
+        self.original_cf_home
+            .clone()
+            .context("could not find the home directory of the cf cli, set CF_HOME")
+    }
+
+    pub(crate) fn get_mcf_folder(&self) -> Arc<PathBuf> {
+        self.mcf_folder
+            .clone()
+            .unwrap_or_else(|| Arc::new(self.options.get_mcf_home_path_buf()))
+    }
+
+    /// Execute the command of the request on its environments, the output and the exit status of
+    /// every environment go to the sink. When all environments are done it fails with the error of
+    /// an environment on which cf could not be executed, or with `McfError::NonZeroExit` when cf
+    /// failed on some of them
+    pub async fn exec(&self, request: &ExecRequest, sink: &mut dyn OutputSink) -> Result<()> {
+        let command = Arc::new(expand(&self.settings, &request.command)?);
+        let environments = known_environments(&request.names, &self.settings)?;
+        let sequential = requires_sequential_mode(&self.settings, &command, &request.sequential);
+        let retries = Retries::new(
+            request.retries,
+            request.retry_delay,
+            &self.settings.retry_patterns,
+        )?;
+        execute(self, environments, command, sequential, retries, sink).await
+    }
+
+    /// The logs of the app on the environments of the request, the log lines of all environments go
+    /// to the sink in timestamp order. Fails with `McfError::NonZeroExit` when cf failed on some of
+    /// the environments
+    pub async fn logs(&self, request: &LogsRequest, sink: &mut dyn OutputSink) -> Result<()> {
+        follow(self, request, sink).await
+    }
+
+    /// The command lines that exec would execute, nothing is executed
+    pub fn dry_run(&self, request: &ExecRequest) -> Result<Vec<String>> {
+        exec_dry_run(
+            &self.settings,
+            &self.options,
+            &request.names,
+            &request.command,
+            &self.get_mcf_folder(),
+            &request.sequential,
+        )
+    }
+}
+
+/// The wait before the first retry of a request
+pub const DEFAULT_RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// A command to execute on environments
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExecRequest {
+    names: String,
+    command: Vec<String>,
+    sequential: bool,
+    retries: u32,
+    retry_delay: Duration,
+}
+
+impl ExecRequest {
+    /// The command (or alias) for the comma separated environment names (example "cf-dev,cf-prod")
+    pub fn new<I, S>(names: impl Into<String>, command: I) -> ExecRequest
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        ExecRequest {
+            names: names.into(),
+            command: command.into_iter().map(Into::into).collect(),
+            sequential: false,
+            retries: 0,
+            retry_delay: DEFAULT_RETRY_DELAY,
+        }
+    }
+
+    /// Execute on one environment at a time, interactive commands always are
+    pub fn sequential(mut self, sequential: bool) -> ExecRequest {
+        self.sequential = sequential;
+        self
+    }
+
+    /// Execute the command again on an environment when cf exits with an error, at most this many
+    /// times. With retry_patterns in the settings only when the output matches one of them
+    pub fn retries(mut self, retries: u32) -> ExecRequest {
+        self.retries = retries;
+        self
+    }
+
+    /// The wait before the first retry, it doubles with every retry
+    pub fn retry_delay(mut self, retry_delay: Duration) -> ExecRequest {
+        self.retry_delay = retry_delay;
+        self
+    }
+
+    pub fn is_sequential(&self) -> bool {
+        self.sequential
+    }
+
+    pub fn names(&self) -> &str {
+        &self.names
+    }
+
+    pub fn command(&self) -> &[String] {
+        &self.command
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::environment::Environment;
+    use crate::error::McfError;
+    use crate::logs::LogFilter;
+    use crate::sink::{ChannelSink, CollectingSink, OutputEvent};
+    use crate::subcommand::SubcommandClasses;
+    use tempfile::tempdir;
+    use tokio::sync::mpsc;
+
+    fn mcf(tempdir: &std::path::Path) -> Mcf {
+        std::fs::create_dir_all(tempdir.join(".cf").join("plugins")).unwrap();
+        Mcf::new(
+            Settings {
+                environments: ["p01", "p02"]
+                    .iter()
+                    .map(|name| Environment {
+                        name: name.to_string(),
+                        url: String::from("url"),
+                        ..Default::default()
+                    })
+                    .collect(),
+                aliases: [(
+                    String::from("hi"),
+                    vec![String::from("Hello"), String::from("$1")],
+                )]
+                .into(),
+                subcommands: [(String::from("hello"), SubcommandClasses::default())].into(),
+                ..Default::default()
+            },
+            Options {
+                cf_binary_name: String::from("echo"),
+                mcf_home: tempdir.to_string_lossy().to_string(),
+            },
+        )
+        .original_cf_home(tempdir.join(".cf"))
+    }
+
+    #[tokio::test]
+    async fn test_exec_collects_lines_and_exit_statuses() {
+        let tempdir: PathBuf = tempdir().unwrap().keep();
+        let mut sink = CollectingSink::new();
+        mcf(&tempdir)
+            .exec(&ExecRequest::new("p01,p02", ["hi", "World"]), &mut sink)
+            .await
+            .unwrap();
+        assert_eq!(sink.output("p01"), vec!["Hello World"]);
+        assert_eq!(sink.output("p02"), vec!["Hello World"]);
+        assert!(sink.status("p01").unwrap().success());
+        assert!(sink.status("p02").unwrap().success());
+        assert!(tempdir.join("homes").join("p01").exists());
+    }
+
+    #[tokio::test]
+    async fn test_exec_sends_to_channel() {
+        let tempdir: PathBuf = tempdir().unwrap().keep();
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        let mut sink = ChannelSink::new(sender);
+        mcf(&tempdir)
+            .exec(&ExecRequest::new("p02", ["Hello"]), &mut sink)
+            .await
+            .unwrap();
+        drop(sink);
+        let mut events = Vec::new();
+        while let Some(event) = receiver.recv().await {
+            events.push(event);
+        }
+        assert_eq!(events.len(), 2);
+        assert!(matches!(&events[0], OutputEvent::Line(line) if line.line == "Hello"));
+        assert!(
+            matches!(&events[1], OutputEvent::Exit { env_name, status } if env_name == "p02" && status.success())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_exec_fails_on_unknown_environment() {
+        let tempdir: PathBuf = tempdir().unwrap().keep();
+        let mut sink = CollectingSink::new();
+        let result = mcf(&tempdir)
+            .exec(&ExecRequest::new("p01,p03", ["Hello"]), &mut sink)
+            .await;
+        assert!(result.is_err());
+        assert!(sink.lines.is_empty());
+    }
+
+    /// A fake cf that runs the script for every command
+    fn failing_mcf(tempdir: &std::path::Path, retry_patterns: &[&str], script: &str) -> Mcf {
+        let mcf = mcf(tempdir);
+        let cf = tempdir.join("cf");
+        std::fs::write(&cf, format!("#!/bin/sh\n{}\n", script)).unwrap();
+        #[cfg(unix)]
+        std::fs::set_permissions(&cf, std::os::unix::fs::PermissionsExt::from_mode(0o755)).unwrap();
+        Mcf::new(
+            Settings {
+                retry_patterns: retry_patterns.iter().map(|s| s.to_string()).collect(),
+                ..mcf.settings().clone()
+            },
+            Options {
+                cf_binary_name: cf.to_string_lossy().to_string(),
+                mcf_home: tempdir.to_string_lossy().to_string(),
+            },
+        )
+        .original_cf_home(tempdir.join(".cf"))
+    }
+
+    #[cfg(not(target_os = "windows"))]
+    #[tokio::test]
+    async fn test_exec_retries_on_transient_errors() {
+        let tempdir: PathBuf = tempdir().unwrap().keep();
+        let mut sink = CollectingSink::new();
+        let request = ExecRequest::new("p01", ["Hello"])
+            .retries(2)
+            .retry_delay(Duration::ZERO);
+        let error = failing_mcf(&tempdir, &["^502 "], "echo 502 Bad Gateway; exit 3")
+            .exec(&request, &mut sink)
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            McfError::find(&error),
+            Some(McfError::NonZeroExit { failures }) if failures.len() == 1
+        ));
+        assert_eq!(
+            sink.lines
+                .iter()
+                .map(|line| line.attempt)
+                .collect::<Vec<u32>>(),
+            vec![0, 1, 2]
+        );
+        assert_eq!(sink.status("p01").unwrap().code(), Some(3));
+    }
+
+    #[cfg(not(target_os = "windows"))]
+    #[tokio::test]
+    async fn test_exec_does_not_retry_other_errors() {
+        let tempdir: PathBuf = tempdir().unwrap().keep();
+        let mut sink = CollectingSink::new();
+        let request = ExecRequest::new("p01,p02", ["Hello"])
+            .retries(2)
+            .retry_delay(Duration::ZERO);
+        let result = failing_mcf(&tempdir, &["^502 "], "echo 404 Not Found; exit 1")
+            .exec(&request, &mut sink)
+            .await;
+        assert!(result.is_err());
+        assert_eq!(sink.output("p01"), vec!["404 Not Found"]);
+        assert_eq!(sink.output("p02"), vec!["404 Not Found"]);
+        assert_eq!(sink.exits.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_exec_continues_when_cf_can_not_be_executed() {
+        let tempdir: PathBuf = tempdir().unwrap().keep();
+        let mcf = mcf(&tempdir);
+        let mut settings = mcf.settings().clone();
+        settings.environments[0].cf_binary = Some(String::from("mcf-binary-that-does-not-exist"));
+        let mcf = Mcf::new(settings, mcf.get_options().as_ref().clone())
+            .original_cf_home(tempdir.join(".cf"));
+        for sequential in [false, true] {
+            let mut sink = CollectingSink::new();
+            let error = mcf
+                .exec(
+                    &ExecRequest::new("p01,p02", ["Hello"]).sequential(sequential),
+                    &mut sink,
+                )
+                .await
+                .unwrap_err();
+            assert_eq!(error.to_string(), "mcf: cf failed on p01");
+            assert!(matches!(
+                McfError::find(&error),
+                Some(McfError::SpawnFailed { env_name, .. }) if env_name == "p01"
+            ));
+            assert_eq!(sink.errors.len(), 1);
+            assert_eq!(sink.errors[0].0, "p01");
+            assert!(sink.status("p02").unwrap().success());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_exec_refuses_retry_patterns_in_sequential_mode() {
+        let tempdir: PathBuf = tempdir().unwrap().keep();
+        let mut sink = CollectingSink::new();
+        let request = ExecRequest::new("p01", ["delete", "my-app"])
+            .sequential(true)
+            .retries(2);
+        let result = failing_mcf(&tempdir, &["^502 "], "exit 0")
+            .exec(&request, &mut sink)
+            .await;
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .starts_with("mcf: can not retry an interactive command with retry_patterns"));
+        assert!(sink.exits.is_empty());
+    }
+
+    #[cfg(not(target_os = "windows"))]
+    #[tokio::test]
+    async fn test_logs_in_timestamp_order() {
+        let tempdir: PathBuf = tempdir().unwrap().keep();
+        // p01 prints its lines before the older lines of p02
+        let script = tempdir.join("fake-cf");
+        std::fs::write(
+            &script,
+            "#!/bin/sh\n\
+             echo \"Retrieving logs for app $2...\"\n\
+             case \"$CF_HOME\" in\n\
+             *p01) echo '   2022-09-02T15:53:16.16+0200 [RTR/1] OUT p01 line 1'\n\
+                   echo '   2022-09-02T15:53:18.16+0200 [APP/PROC/WEB/0] OUT p01 line 2' ;;\n\
+             *) sleep 0.2\n\
+                echo '   2022-09-02T15:53:17.16+0200 [APP/PROC/WEB/1] OUT p02 line 1' ;;\n\
+             esac\n",
+        )
+        .unwrap();
+        std::fs::set_permissions(&script, std::os::unix::fs::PermissionsExt::from_mode(0o755))
+            .unwrap();
+        let mcf = Mcf::new(
+            mcf(&tempdir).settings().clone(),
+            Options {
+                cf_binary_name: script.to_string_lossy().to_string(),
+                mcf_home: tempdir.to_string_lossy().to_string(),
+            },
+        )
+        .original_cf_home(tempdir.join(".cf"));
+        let mut sink = CollectingSink::new();
+        mcf.logs(&LogsRequest::new("p01,p02", "app").recent(true), &mut sink)
+            .await
+            .unwrap();
+        let lines = sink
+            .lines
+            .iter()
+            .map(|line| line.line.trim_start())
+            .collect::<Vec<&str>>();
+        assert_eq!(
+            lines[2..],
+            [
+                "2022-09-02T15:53:16.16+0200 [RTR/1] OUT p01 line 1",
+                "2022-09-02T15:53:17.16+0200 [APP/PROC/WEB/1] OUT p02 line 1",
+                "2022-09-02T15:53:18.16+0200 [APP/PROC/WEB/0] OUT p01 line 2",
+            ]
+        );
+        assert_eq!(sink.exits.len(), 2);
+        let mut sink = CollectingSink::new();
+        let filter = LogFilter {
+            source_types: vec![String::from("APP")],
+            instances: vec![0],
+            ..Default::default()
+        };
+        mcf.logs(
+            &LogsRequest::new("p01,p02", "app")
+                .recent(true)
+                .filter(filter),
+            &mut sink,
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            sink.output("p01")[1].trim_start(),
+            "2022-09-02T15:53:18.16+0200 [APP/PROC/WEB/0] OUT p01 line 2"
+        );
+        assert_eq!(sink.output("p02").len(), 1);
+    }
+
+    #[test]
+    fn test_dry_run() {
+        let tempdir: PathBuf = tempdir().unwrap().keep();
+        let result = mcf(&tempdir)
+            .mcf_folder("/mcf")
+            .dry_run(&ExecRequest::new("p01", ["apps"]).sequential(true))
+            .unwrap();
+        assert_eq!(
+            result,
+            vec![
+                String::from("mcf: dry run in sequential mode, nothing is executed"),
+                format!(
+                    "p01 | CF_HOME={} echo apps",
+                    PathBuf::from("/mcf").join("homes").join("p01").display()
+                )
+            ]
+        );
+    }
+}
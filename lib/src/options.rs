@@ -1,5 +1,12 @@
 use std::path::PathBuf;
 
+use anyhow::{Context, Result};
+
+/// Environment variable for the mcf config path, the --override-path flag takes precedence
+pub const MCF_HOME_ENV: &str = "MCF_HOME";
+/// Environment variable for the cf binary name, the --cf-binary-name flag takes precedence
+pub const MCF_CF_BINARY_ENV: &str = "MCF_CF_BINARY";
+
 #[derive(Debug, Clone)]
 pub struct Options {
     pub cf_binary_name: String,
@@ -11,33 +18,65 @@ impl Options {
         PathBuf::from(&self.mcf_home)
     }
 
-    pub fn new(cf_binary_name: Option<String>, mcf_home: Option<String>) -> Options {
-        let mut options = Options::default();
-
-        if let Some(some) = mcf_home {
-            options.mcf_home = some;
-        }
-
-        if let Some(some) = cf_binary_name {
-            options.cf_binary_name = some;
-        }
+    /// Every value is taken from the given flag, then from its environment variable and then
+    /// from the default, fails when there is no mcf home at all
+    pub fn new(cf_binary_name: Option<String>, mcf_home: Option<String>) -> Result<Options> {
+        Options::from_sources(
+            cf_binary_name,
+            mcf_home,
+            |key| std::env::var(key).ok(),
+            default_mcf_home(dirs::home_dir(), dirs::config_dir()),
+        )
+    }
 
-        options
+    fn from_sources<F>(
+        cf_binary_name: Option<String>,
+        mcf_home: Option<String>,
+        env: F,
+        default_mcf_home: Option<PathBuf>,
+    ) -> Result<Options>
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        let env = |key: &str| env(key).filter(|value| !value.is_empty());
+        let mcf_home = mcf_home
+            .or_else(|| env(MCF_HOME_ENV))
+            .or_else(|| default_mcf_home.map(|dir| dir.to_string_lossy().to_string()))
+            .with_context(|| {
+                format!(
+                    "could not find a home directory for mcf, set {} or --override-path",
+                    MCF_HOME_ENV
+                )
+            })?;
+        Ok(Options {
+            cf_binary_name: cf_binary_name
+                .or_else(|| env(MCF_CF_BINARY_ENV))
+                .unwrap_or_else(|| String::from("cf")),
+            mcf_home,
+        })
     }
 }
 
-impl Default for Options {
-    fn default() -> Options {
-        Options {
-            cf_binary_name: "cf".to_string(),
-            mcf_home: dirs::home_dir()
-                .expect("OS not supported")
-                .join(".mcf")
-                .to_str()
-                .unwrap()
-                .to_string(),
-        }
-    }
+/// The .cf folder of the cf cli itself, in $CF_HOME when it is set like the cf cli does
+pub fn default_cf_home() -> Option<PathBuf> {
+    std::env::var_os("CF_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(dirs::home_dir)
+        .map(|dir| dir.join(".cf"))
+}
+
+/// ~/.mcf, or the XDG config directory when there is no home directory (as in some minimal
+/// containers). The shared temp directory is never used, other users could read the tokens there
+fn default_mcf_home(home_dir: Option<PathBuf>, config_dir: Option<PathBuf>) -> Option<PathBuf> {
+    home_dir
+        .map(|dir| dir.join(".mcf"))
+        .or_else(|| config_dir.map(|dir| dir.join("mcf")))
+        .or_else(|| {
+            std::env::var_os("XDG_CONFIG_HOME")
+                .filter(|dir| !dir.is_empty())
+                .map(|dir| PathBuf::from(dir).join("mcf"))
+        })
 }
 
 #[cfg(test)]
@@ -46,7 +85,13 @@ mod tests {
 
     #[test]
     fn test_get_mcf_home_path_buf() {
-        let option = Options::default();
+        let option = Options::from_sources(
+            None,
+            None,
+            |_| None,
+            dirs::home_dir().map(|dir| dir.join(".mcf")),
+        )
+        .unwrap();
         assert_eq!(
             option.get_mcf_home_path_buf(),
             PathBuf::from(
@@ -59,4 +104,62 @@ mod tests {
             )
         );
     }
+
+    #[test]
+    fn test_options_from_environment_variables() {
+        let env = |key: &str| match key {
+            MCF_HOME_ENV => Some(String::from("/env/mcf")),
+            MCF_CF_BINARY_ENV => Some(String::from("cf8")),
+            _ => None,
+        };
+        let options = Options::from_sources(None, None, env, None).unwrap();
+        assert_eq!(options.mcf_home, "/env/mcf");
+        assert_eq!(options.cf_binary_name, "cf8");
+        let options = Options::from_sources(
+            Some(String::from("cf7")),
+            Some(String::from("/flag/mcf")),
+            env,
+            None,
+        )
+        .unwrap();
+        assert_eq!(options.mcf_home, "/flag/mcf");
+        assert_eq!(options.cf_binary_name, "cf7");
+    }
+
+    #[test]
+    fn test_options_ignore_empty_environment_variables() {
+        let options = Options::from_sources(
+            None,
+            None,
+            |_| Some(String::new()),
+            Some(PathBuf::from("/home/user/.mcf")),
+        )
+        .unwrap();
+        assert_eq!(options.cf_binary_name, "cf");
+        assert_eq!(options.mcf_home, "/home/user/.mcf");
+    }
+
+    #[test]
+    fn test_options_without_mcf_home() {
+        let result = Options::from_sources(None, None, |_| None, None);
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "could not find a home directory for mcf, set MCF_HOME or --override-path"
+        );
+        let options =
+            Options::from_sources(None, Some(String::from("/flag/mcf")), |_| None, None).unwrap();
+        assert_eq!(options.mcf_home, "/flag/mcf");
+    }
+
+    #[test]
+    fn test_default_mcf_home_without_home_dir() {
+        assert_eq!(
+            default_mcf_home(Some(PathBuf::from("/home/user")), None),
+            Some(PathBuf::from("/home/user/.mcf"))
+        );
+        assert_eq!(
+            default_mcf_home(None, Some(PathBuf::from("/config"))),
+            Some(PathBuf::from("/config/mcf"))
+        );
+    }
 }
@@ -0,0 +1,590 @@
+use std::io::{BufRead, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use strum::{AsRefStr, Display};
+
+use crate::cf::get_cf_home_from_mcf_environment;
+use crate::environment::{update, Environment};
+use crate::error::McfError;
+use crate::options::Options;
+use crate::settings::Settings;
+
+const PLUGINS_DIRECTORY: &str = "plugins";
+const PLUGINS_CONFIG: &str = "config.json";
+
+/// How the plugin directory of an environment relates to the shared plugin directory
+#[derive(Debug, Clone, Copy, PartialEq, Eq, AsRefStr, Display)]
+#[strum(serialize_all = "kebab-case")]
+pub enum PluginState {
+    /// Linked to the shared plugin directory
+    Shared,
+    /// Has its own plugin directory, because the environment is isolated
+    Isolated,
+    /// There is no shared plugin directory, so there is nothing to link
+    NoSharedPlugins,
+    /// The plugin directory is created by the next command on the environment
+    Missing,
+    /// Linked to another directory than the shared plugin directory
+    WrongLink,
+    /// Has its own plugin directory, but the environment is not isolated
+    NotShared,
+}
+
+/// The plugin directory of an environment and the plugins that are registered in it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Plugins {
+    pub env_name: String,
+    pub directory: PathBuf,
+    pub state: PluginState,
+    pub registered: Vec<String>,
+}
+
+/// The plugin directory that cf uses for an environment
+pub fn plugin_directory(name: &String, mcf_folder: &Path) -> PathBuf {
+    get_cf_home_from_mcf_environment(name, mcf_folder)
+        .join(".cf")
+        .join(PLUGINS_DIRECTORY)
+}
+
+pub fn state(environment: &Environment, original_cf_home: &Path, mcf_folder: &Path) -> PluginState {
+    let source = original_cf_home.join(PLUGINS_DIRECTORY);
+    let destination = plugin_directory(&environment.name, mcf_folder);
+    match (
+        std::fs::symlink_metadata(&destination),
+        environment.isolated_plugins,
+    ) {
+        (Ok(metadata), true) if metadata.is_dir() => PluginState::Isolated,
+        (Ok(metadata), false) if metadata.is_symlink() => match std::fs::read_link(&destination) {
+            Ok(target) if target != source => PluginState::WrongLink,
+            _ if !source.exists() => PluginState::NoSharedPlugins,
+            _ => PluginState::Shared,
+        },
+        (Ok(_), false) => PluginState::NotShared,
+        (_, false) if !source.exists() => PluginState::NoSharedPlugins,
+        _ => PluginState::Missing,
+    }
+}
+
+/// The names of the plugins in the config.json of a plugin directory
+pub fn registered_plugins(directory: &Path) -> Result<Vec<String>> {
+    let config = directory.join(PLUGINS_CONFIG);
+    if !config.exists() {
+        return Ok(Vec::new());
+    }
+    let value: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&config)?)
+        .with_context(|| format!("could not parse {:?}", config))?;
+    Ok(value
+        .get("Plugins")
+        .and_then(serde_json::Value::as_object)
+        .map(|plugins| plugins.keys().cloned().collect())
+        .unwrap_or_default())
+}
+
+pub fn list(settings: &Settings, original_cf_home: &Path, mcf_folder: &Path) -> Vec<Plugins> {
+    settings
+        .environments
+        .iter()
+        .map(|environment| {
+            let directory = plugin_directory(&environment.name, mcf_folder);
+            Plugins {
+                env_name: environment.name.clone(),
+                state: state(environment, original_cf_home, mcf_folder),
+                registered: registered_plugins(&directory).unwrap_or_default(),
+                directory,
+            }
+        })
+        .collect()
+}
+
+/// Link the plugin directory of an environment to the shared plugin directory, or give an isolated
+/// environment its own plugin directory. Nothing is changed when the directory is already right.
+pub(crate) fn prepare_plugins(
+    environment: &Environment,
+    original_cf_home: &Path,
+    mcf_folder: &Path,
+) -> Result<()> {
+    let source = original_cf_home.join(PLUGINS_DIRECTORY);
+    let destination = plugin_directory(&environment.name, mcf_folder);
+    if environment.isolated_plugins {
+        return own_directory(&source, &destination);
+    }
+    match std::fs::symlink_metadata(&destination) {
+        Ok(metadata) if metadata.is_symlink() => {
+            if std::fs::read_link(&destination)? == source && source.exists() {
+                return Ok(());
+            }
+            remove_symlink(&destination)?;
+        }
+        Ok(metadata) if metadata.is_dir() => {
+            if std::fs::read_dir(&destination)?.next().is_some() {
+                bail!(McfError::PluginsNotShared {
+                    env_name: environment.name.clone(),
+                    directory: destination,
+                });
+            }
+            std::fs::remove_dir(&destination)?;
+        }
+        Ok(_) => std::fs::remove_file(&destination)?,
+        Err(_) => {}
+    }
+    // without shared plugins cf just has no plugins
+    if !source.exists() {
+        return Ok(());
+    }
+    std::fs::create_dir_all(
+        destination
+            .parent()
+            .context("plugin directory has no parent")?,
+    )?;
+    create_symlink(source, destination)
+}
+
+/// Repair the plugin directories of all environments, an own plugin directory of an environment
+/// that is not isolated is replaced by a link to the shared plugin directory. The directories that
+/// are removed are listed first, without `force` the user has to confirm it on a terminal
+pub fn sync(
+    settings: &Settings,
+    original_cf_home: &Path,
+    mcf_folder: &Path,
+    force: &bool,
+) -> Result<()> {
+    sync_confirmed(
+        settings,
+        original_cf_home,
+        mcf_folder,
+        force,
+        std::io::stdin().is_terminal(),
+        &mut std::io::stdin().lock(),
+        &mut std::io::stderr(),
+    )
+}
+
+fn sync_confirmed<R: BufRead, W: Write>(
+    settings: &Settings,
+    original_cf_home: &Path,
+    mcf_folder: &Path,
+    force: &bool,
+    is_terminal: bool,
+    input: &mut R,
+    output: &mut W,
+) -> Result<()> {
+    let source = original_cf_home.join(PLUGINS_DIRECTORY);
+    let not_shared = list(settings, original_cf_home, mcf_folder)
+        .into_iter()
+        .filter(|plugins| plugins.state == PluginState::NotShared)
+        .collect::<Vec<Plugins>>();
+    if let Some(plugins) = not_shared.first() {
+        if !source.exists() {
+            bail!(McfError::PluginsMissing {
+                env_name: plugins.env_name.clone(),
+                directory: source,
+            });
+        }
+        confirm_removal(&not_shared, force, is_terminal, input, output)?;
+    }
+    for plugins in &not_shared {
+        remove_directory(&plugins.directory)?;
+    }
+    for environment in &settings.environments {
+        prepare_plugins(environment, original_cf_home, mcf_folder)?;
+    }
+    Ok(())
+}
+
+fn confirm_removal<R: BufRead, W: Write>(
+    not_shared: &[Plugins],
+    force: &bool,
+    is_terminal: bool,
+    input: &mut R,
+    output: &mut W,
+) -> Result<()> {
+    writeln!(output, "mcf: these own plugin directories are removed:")?;
+    for plugins in not_shared {
+        writeln!(
+            output,
+            "  {}: {:?} (registered: {})",
+            plugins.env_name,
+            plugins.directory,
+            plugins.registered.join(", ")
+        )?;
+    }
+    if *force {
+        return Ok(());
+    }
+    if !is_terminal {
+        bail!("mcf: refusing to remove plugin directories without a terminal, pass --force to remove them anyway");
+    }
+    write!(output, "mcf: type \"yes\" to remove them: ")?;
+    output.flush()?;
+    let mut answer = String::new();
+    input.read_line(&mut answer)?;
+    if answer.trim() != "yes" {
+        bail!("mcf: removal of the plugin directories was not confirmed, nothing was changed");
+    }
+    Ok(())
+}
+
+/// Give an environment its own plugin directory, it starts with a copy of the shared plugins
+pub fn isolate(
+    options: &Options,
+    settings: &Settings,
+    name: &String,
+    original_cf_home: &Path,
+    mcf_folder: &Path,
+) -> Result<()> {
+    let environment = settings
+        .get_environment_by_name(name)
+        .ok_or_else(|| McfError::unknown_environment(name, &settings.environments))?;
+    update(options, name, |environment| {
+        environment.isolated_plugins = true
+    })?;
+    prepare_plugins(
+        &Environment {
+            isolated_plugins: true,
+            ..environment
+        },
+        original_cf_home,
+        mcf_folder,
+    )
+}
+
+/// Use the shared plugin directory again for an isolated environment, its own plugin directory is
+/// removed
+pub fn share(
+    options: &Options,
+    settings: &Settings,
+    name: &String,
+    original_cf_home: &Path,
+    mcf_folder: &Path,
+) -> Result<()> {
+    let environment = settings
+        .get_environment_by_name(name)
+        .ok_or_else(|| McfError::unknown_environment(name, &settings.environments))?;
+    let source = original_cf_home.join(PLUGINS_DIRECTORY);
+    if !source.exists() {
+        bail!(McfError::PluginsMissing {
+            env_name: name.clone(),
+            directory: source,
+        });
+    }
+    update(options, name, |environment| {
+        environment.isolated_plugins = false
+    })?;
+    let destination = plugin_directory(name, mcf_folder);
+    if std::fs::symlink_metadata(&destination).is_ok_and(|metadata| metadata.is_dir()) {
+        remove_directory(&destination)?;
+    }
+    prepare_plugins(
+        &Environment {
+            isolated_plugins: false,
+            ..environment
+        },
+        original_cf_home,
+        mcf_folder,
+    )
+}
+
+/// Replace a link (or file) with an own directory, which starts with a copy of the shared plugins
+/// and their registrations
+fn own_directory(source: &Path, destination: &Path) -> Result<()> {
+    match std::fs::symlink_metadata(destination) {
+        Ok(metadata) if metadata.is_dir() => return Ok(()),
+        Ok(metadata) if metadata.is_symlink() => remove_symlink(destination)?,
+        Ok(_) => std::fs::remove_file(destination)?,
+        Err(_) => {}
+    }
+    if source.is_dir() {
+        copy_directory(source, destination)?;
+        relocate_plugins(source, destination)
+    } else {
+        std::fs::create_dir_all(destination).context("could not create plugin directory")
+    }
+}
+
+fn copy_directory(source: &Path, destination: &Path) -> Result<()> {
+    std::fs::create_dir_all(destination)?;
+    for entry in std::fs::read_dir(source)? {
+        let entry = entry?;
+        let target = destination.join(entry.file_name());
+        if entry.path().is_dir() {
+            copy_directory(&entry.path(), &target)?;
+        } else {
+            std::fs::copy(entry.path(), &target)
+                .with_context(|| format!("could not copy {:?}", entry.path()))?;
+        }
+    }
+    Ok(())
+}
+
+/// Point the locations of the registered plugins to the copied binaries
+fn relocate_plugins(source: &Path, destination: &Path) -> Result<()> {
+    let config = destination.join(PLUGINS_CONFIG);
+    if !config.exists() {
+        return Ok(());
+    }
+    let mut value: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&config)?)
+        .with_context(|| format!("could not parse {:?}", config))?;
+    if let Some(plugins) = value
+        .get_mut("Plugins")
+        .and_then(serde_json::Value::as_object_mut)
+    {
+        for plugin in plugins.values_mut() {
+            let Some(location) = plugin.get("Location").and_then(serde_json::Value::as_str) else {
+                continue;
+            };
+            if let Ok(relative) = Path::new(location).strip_prefix(source) {
+                plugin["Location"] =
+                    serde_json::Value::from(destination.join(relative).to_string_lossy());
+            }
+        }
+    }
+    std::fs::write(&config, serde_json::to_string_pretty(&value)?)?;
+    Ok(())
+}
+
+fn remove_directory(directory: &Path) -> Result<()> {
+    std::fs::remove_dir_all(directory)
+        .with_context(|| format!("could not remove plugin directory {:?}", directory))
+}
+
+#[cfg(target_os = "windows")]
+fn create_symlink<P: AsRef<Path>, Q: AsRef<Path>>(source: P, destination: Q) -> Result<()> {
+    std::os::windows::fs::symlink_dir(source, destination).context("Symlink creation failed")
+}
+
+#[cfg(not(target_os = "windows"))]
+fn create_symlink<P: AsRef<Path>, Q: AsRef<Path>>(source: P, destination: Q) -> Result<()> {
+    std::os::unix::fs::symlink(source, destination).context("Symlink creation failed")
+}
+
+#[cfg(target_os = "windows")]
+fn remove_symlink(path: &Path) -> Result<()> {
+    std::fs::remove_dir(path).context("Symlink removal failed")
+}
+
+#[cfg(not(target_os = "windows"))]
+fn remove_symlink(path: &Path) -> Result<()> {
+    std::fs::remove_file(path).context("Symlink removal failed")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn environment(isolated_plugins: bool) -> Environment {
+        Environment {
+            name: String::from("envname"),
+            url: String::from("https://api.example.com"),
+            isolated_plugins,
+            ..Default::default()
+        }
+    }
+
+    fn shared_plugins(tempdir: &Path) -> PathBuf {
+        let source = tempdir.join(".cf").join("plugins");
+        std::fs::create_dir_all(&source).unwrap();
+        std::fs::write(source.join("test-plugin"), "binary").unwrap();
+        std::fs::write(
+            source.join("config.json"),
+            format!(
+                r#"{{"SchemaVersion": 1, "Plugins": {{"test-plugin": {{"Location": "{}"}}}}}}"#,
+                source.join("test-plugin").display()
+            ),
+        )
+        .unwrap();
+        source
+    }
+
+    #[test]
+    fn test_prepare_plugins_if_happy_case() {
+        let tempdir: PathBuf = tempdir().unwrap().keep();
+        let source = shared_plugins(&tempdir);
+        let mcf_folder = tempdir.join("mcf-lib-home");
+        let result = prepare_plugins(&environment(false), &tempdir.join(".cf"), &mcf_folder);
+        assert!(result.is_ok());
+        let destination = plugin_directory(&String::from("envname"), &mcf_folder);
+        assert!(destination.is_symlink());
+        assert_eq!(std::fs::read_link(&destination).unwrap(), source);
+        assert!(destination.join("test-plugin").exists());
+        assert_eq!(
+            state(&environment(false), &tempdir.join(".cf"), &mcf_folder),
+            PluginState::Shared
+        );
+        assert_eq!(
+            registered_plugins(&destination).unwrap(),
+            vec!["test-plugin"]
+        );
+    }
+
+    #[test]
+    fn test_prepare_plugins_if_source_does_not_exist() {
+        let tempdir: PathBuf = tempdir().unwrap().keep();
+        let mcf_folder = tempdir.join("mcf-lib-home");
+        let result = prepare_plugins(&environment(false), &tempdir.join(".cf"), &mcf_folder);
+        assert!(result.is_ok());
+        assert!(!plugin_directory(&String::from("envname"), &mcf_folder).exists());
+        assert_eq!(
+            state(&environment(false), &tempdir.join(".cf"), &mcf_folder),
+            PluginState::NoSharedPlugins
+        );
+    }
+
+    #[test]
+    fn test_prepare_plugins_repairs_wrong_link() {
+        let tempdir: PathBuf = tempdir().unwrap().keep();
+        let source = shared_plugins(&tempdir);
+        let mcf_folder = tempdir.join("mcf-lib-home");
+        let destination = plugin_directory(&String::from("envname"), &mcf_folder);
+        std::fs::create_dir_all(destination.parent().unwrap()).unwrap();
+        create_symlink(tempdir.join("elsewhere"), &destination).unwrap();
+        assert_eq!(
+            state(&environment(false), &tempdir.join(".cf"), &mcf_folder),
+            PluginState::WrongLink
+        );
+        prepare_plugins(&environment(false), &tempdir.join(".cf"), &mcf_folder).unwrap();
+        assert_eq!(std::fs::read_link(&destination).unwrap(), source);
+    }
+
+    #[test]
+    fn test_prepare_plugins_if_empty_folder_exists() {
+        let tempdir: PathBuf = tempdir().unwrap().keep();
+        shared_plugins(&tempdir);
+        let mcf_folder = tempdir.join("mcf-lib-home");
+        let destination = plugin_directory(&String::from("envname"), &mcf_folder);
+        std::fs::create_dir_all(&destination).unwrap();
+        let result = prepare_plugins(&environment(false), &tempdir.join(".cf"), &mcf_folder);
+        assert!(result.is_ok());
+        assert!(destination.is_symlink());
+    }
+
+    #[test]
+    fn test_prepare_plugins_if_own_plugins_exist() {
+        let tempdir: PathBuf = tempdir().unwrap().keep();
+        shared_plugins(&tempdir);
+        let mcf_folder = tempdir.join("mcf-lib-home");
+        let destination = plugin_directory(&String::from("envname"), &mcf_folder);
+        std::fs::create_dir_all(&destination).unwrap();
+        std::fs::write(destination.join("own-plugin"), "binary").unwrap();
+        let result = prepare_plugins(&environment(false), &tempdir.join(".cf"), &mcf_folder);
+        let error = result.unwrap_err();
+        assert!(matches!(
+            McfError::find(&error),
+            Some(McfError::PluginsNotShared { env_name, .. }) if env_name == "envname"
+        ));
+        assert!(McfError::find(&error)
+            .and_then(McfError::hint)
+            .unwrap()
+            .contains("run \"mcf plugins sync\""));
+        assert!(destination.join("own-plugin").exists());
+        assert_eq!(
+            state(&environment(false), &tempdir.join(".cf"), &mcf_folder),
+            PluginState::NotShared
+        );
+    }
+
+    #[test]
+    fn test_prepare_plugins_if_file_exists() {
+        let tempdir: PathBuf = tempdir().unwrap().keep();
+        shared_plugins(&tempdir);
+        let mcf_folder = tempdir.join("mcf-lib-home");
+        let destination = plugin_directory(&String::from("envname"), &mcf_folder);
+        std::fs::create_dir_all(destination.parent().unwrap()).unwrap();
+        std::fs::File::create(&destination).unwrap();
+        let result = prepare_plugins(&environment(false), &tempdir.join(".cf"), &mcf_folder);
+        assert!(result.is_ok());
+        assert!(destination.is_symlink());
+    }
+
+    #[test]
+    fn test_prepare_plugins_if_isolated() {
+        let tempdir: PathBuf = tempdir().unwrap().keep();
+        let source = shared_plugins(&tempdir);
+        let mcf_folder = tempdir.join("mcf-lib-home");
+        prepare_plugins(&environment(false), &tempdir.join(".cf"), &mcf_folder).unwrap();
+        prepare_plugins(&environment(true), &tempdir.join(".cf"), &mcf_folder).unwrap();
+        let destination = plugin_directory(&String::from("envname"), &mcf_folder);
+        assert!(!destination.is_symlink());
+        assert!(destination.join("test-plugin").exists());
+        assert!(std::fs::read_to_string(destination.join("config.json"))
+            .unwrap()
+            .contains(&destination.join("test-plugin").display().to_string()));
+        assert!(source.join("test-plugin").exists());
+        assert_eq!(
+            state(&environment(true), &tempdir.join(".cf"), &mcf_folder),
+            PluginState::Isolated
+        );
+    }
+
+    #[test]
+    fn test_sync_shares_own_plugins_again() {
+        let tempdir: PathBuf = tempdir().unwrap().keep();
+        let source = shared_plugins(&tempdir);
+        let mcf_folder = tempdir.join("mcf-lib-home");
+        let settings = Settings {
+            environments: vec![environment(false)],
+            ..Default::default()
+        };
+        let destination = plugin_directory(&String::from("envname"), &mcf_folder);
+        std::fs::create_dir_all(&destination).unwrap();
+        std::fs::write(destination.join("own-plugin"), "binary").unwrap();
+        let mut output = Vec::new();
+        sync_confirmed(
+            &settings,
+            &tempdir.join(".cf"),
+            &mcf_folder,
+            &false,
+            true,
+            &mut "yes\n".as_bytes(),
+            &mut output,
+        )
+        .unwrap();
+        assert!(String::from_utf8(output)
+            .unwrap()
+            .contains(&format!("envname: {:?}", destination)));
+        assert_eq!(std::fs::read_link(&destination).unwrap(), source);
+        assert_eq!(
+            list(&settings, &tempdir.join(".cf"), &mcf_folder),
+            vec![Plugins {
+                env_name: String::from("envname"),
+                directory: destination,
+                state: PluginState::Shared,
+                registered: vec![String::from("test-plugin")],
+            }]
+        );
+    }
+
+    #[test]
+    fn test_sync_without_confirmation() {
+        let tempdir: PathBuf = tempdir().unwrap().keep();
+        shared_plugins(&tempdir);
+        let mcf_folder = tempdir.join("mcf-lib-home");
+        let settings = Settings {
+            environments: vec![environment(false)],
+            ..Default::default()
+        };
+        let destination = plugin_directory(&String::from("envname"), &mcf_folder);
+        std::fs::create_dir_all(&destination).unwrap();
+        std::fs::write(destination.join("own-plugin"), "binary").unwrap();
+        let sync = |force: &bool, is_terminal: bool, answer: &str| {
+            sync_confirmed(
+                &settings,
+                &tempdir.join(".cf"),
+                &mcf_folder,
+                force,
+                is_terminal,
+                &mut answer.as_bytes(),
+                &mut Vec::new(),
+            )
+        };
+        assert!(sync(&false, true, "no\n").is_err());
+        assert!(sync(&false, false, "yes\n")
+            .unwrap_err()
+            .to_string()
+            .contains("pass --force"));
+        assert!(destination.join("own-plugin").exists());
+        sync(&true, false, "").unwrap();
+        assert!(destination.is_symlink());
+    }
+}
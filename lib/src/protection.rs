@@ -0,0 +1,203 @@
+use std::io::{BufRead, IsTerminal, Write};
+
+use anyhow::{bail, Result};
+
+use crate::alias::expand;
+use crate::environment::Environment;
+use crate::settings::Settings;
+use crate::subcommand::classify;
+
+/// The protected environments that would be changed by the command, a subcommand that mcf does not
+/// know may change them as well
+pub fn protected_environments<'a>(
+    settings: &Settings,
+    environments: &'a [Environment],
+    command: &[String],
+) -> Result<Vec<&'a Environment>> {
+    let classification = classify(settings, &expand(settings, command)?);
+    if !classification.mutating && !classification.unknown {
+        return Ok(Vec::new());
+    }
+    Ok(environments.iter().filter(|env| env.protected).collect())
+}
+
+/// Ask the user to type the name of every protected environment that would be changed by the
+/// command, without a terminal this is refused unless `yes_i_mean_prod` is set
+pub fn confirm_protected_environments(
+    settings: &Settings,
+    environments: &[Environment],
+    command: &[String],
+    yes_i_mean_prod: &bool,
+) -> Result<()> {
+    confirm(
+        &protected_environments(settings, environments, command)?,
+        command,
+        yes_i_mean_prod,
+        std::io::stdin().is_terminal(),
+        &mut std::io::stdin().lock(),
+        &mut std::io::stderr(),
+    )
+}
+
+fn confirm<R: BufRead, W: Write>(
+    protected: &[&Environment],
+    command: &[String],
+    yes_i_mean_prod: &bool,
+    is_terminal: bool,
+    input: &mut R,
+    output: &mut W,
+) -> Result<()> {
+    if protected.is_empty() || *yes_i_mean_prod {
+        return Ok(());
+    }
+    let names = protected
+        .iter()
+        .map(|env| env.name.clone())
+        .collect::<Vec<String>>()
+        .join(", ");
+    if !is_terminal {
+        bail!(
+            "mcf: refusing to execute {:?} on protected environment(s) {} without a terminal, pass --yes-i-mean-prod to execute it anyway",
+            command.join(" "),
+            names
+        );
+    }
+    for environment in protected {
+        write!(
+            output,
+            "mcf: {:?} changes protected environment {}, type its name to confirm: ",
+            command.join(" "),
+            environment.name
+        )?;
+        output.flush()?;
+        let mut answer = String::new();
+        input.read_line(&mut answer)?;
+        if answer.trim() != environment.name {
+            bail!(
+                "mcf: confirmation for protected environment {} did not match, nothing was executed",
+                environment.name
+            );
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    fn strings(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+
+    fn environments() -> Vec<Environment> {
+        vec![
+            Environment {
+                name: String::from("dev"),
+                ..Default::default()
+            },
+            Environment {
+                name: String::from("prod"),
+                protected: true,
+                ..Default::default()
+            },
+        ]
+    }
+
+    #[test]
+    fn test_protected_environments_expands_aliases() {
+        let settings = Settings {
+            aliases: BTreeMap::from([(String::from("rm-app"), strings(&["delete", "-f"]))]),
+            ..Default::default()
+        };
+        let environments = environments();
+        let result =
+            protected_environments(&settings, &environments, &strings(&["rm-app", "x"])).unwrap();
+        assert_eq!(result, vec![&environments[1]]);
+        let result = protected_environments(&settings, &environments, &strings(&["apps"])).unwrap();
+        assert!(result.is_empty());
+    }
+
+    #[test]
+    fn test_protected_environments_of_unknown_and_mutating_commands() {
+        let settings = Settings::default();
+        let environments = environments();
+        for command in [
+            &["curl", "-X", "DELETE", "/v3/apps/guid"][..],
+            &["uups", "my-service", "-p", "{}"],
+            &["my-plugin-command", "my-app"],
+        ] {
+            assert_eq!(
+                protected_environments(&settings, &environments, &strings(command)).unwrap(),
+                vec![&environments[1]],
+                "{:?}",
+                command
+            );
+        }
+        let result =
+            protected_environments(&settings, &environments, &strings(&["curl", "/v3/apps"]))
+                .unwrap();
+        assert!(result.is_empty());
+    }
+
+    #[test]
+    fn test_confirm_with_matching_name() {
+        let environments = environments();
+        let mut output = Vec::new();
+        let result = confirm(
+            &[&environments[1]],
+            &strings(&["delete", "my-app"]),
+            &false,
+            true,
+            &mut "prod\n".as_bytes(),
+            &mut output,
+        );
+        assert!(result.is_ok());
+        assert!(String::from_utf8(output)
+            .unwrap()
+            .contains("type its name to confirm"));
+    }
+
+    #[test]
+    fn test_confirm_with_wrong_name() {
+        let environments = environments();
+        let result = confirm(
+            &[&environments[1]],
+            &strings(&["delete", "my-app"]),
+            &false,
+            true,
+            &mut "dev\n".as_bytes(),
+            &mut Vec::new(),
+        );
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_confirm_without_terminal() {
+        let environments = environments();
+        let command = strings(&["delete", "my-app"]);
+        let result = confirm(
+            &[&environments[1]],
+            &command,
+            &false,
+            false,
+            &mut "prod\n".as_bytes(),
+            &mut Vec::new(),
+        );
+        assert!(result.is_err());
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .contains("pass --yes-i-mean-prod"));
+        let result = confirm(
+            &[&environments[1]],
+            &command,
+            &true,
+            false,
+            &mut "".as_bytes(),
+            &mut Vec::new(),
+        );
+        assert!(result.is_ok());
+    }
+}
@@ -0,0 +1,658 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::task::JoinSet;
+
+use crate::alias::expand;
+use crate::cf::child_tokio;
+use crate::environment::Environment;
+use crate::error::McfError;
+use crate::exec::{known_environments, line_prefix, max_environment_name_length};
+use crate::options::Options;
+use crate::protection;
+use crate::settings::Settings;
+use crate::subcommand::classify;
+
+/// A list of steps that is executed in order for every environment
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Default, Clone)]
+pub struct Runbook {
+    /// Variables that can be used in the steps with ${name}, ${env} is always the environment name
+    #[serde(default)]
+    pub variables: BTreeMap<String, String>,
+    pub steps: Vec<Step>,
+}
+
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Default, Clone)]
+pub struct Step {
+    #[serde(default)]
+    pub name: Option<String>,
+    /// cf command (or alias) to execute, for example ["stop", "${app}"]
+    #[serde(default)]
+    pub command: Vec<String>,
+    /// Seconds to wait instead of executing a command
+    #[serde(default)]
+    pub sleep: Option<u64>,
+    /// Continue with the next step when this step fails
+    #[serde(default)]
+    pub continue_on_error: bool,
+    /// Variables captured from the output of this step, the value is a regex and the first group
+    /// (or the whole match when there is no group) is stored
+    #[serde(default)]
+    pub capture: BTreeMap<String, String>,
+}
+
+impl Step {
+    fn description(&self) -> String {
+        match (&self.name, self.sleep) {
+            (Some(some), _) => some.clone(),
+            (None, Some(some)) => format!("sleep {}s", some),
+            (None, None) => self.command.join(" "),
+        }
+    }
+}
+
+impl Runbook {
+    pub fn load(path: &Path) -> Result<Runbook> {
+        let runbook: Runbook = serde_yaml::from_str(
+            &fs::read_to_string(path).with_context(|| format!("could not read {:?}", path))?,
+        )
+        .with_context(|| format!("could not parse runbook {:?}", path))?;
+        runbook.validate()?;
+        Ok(runbook)
+    }
+
+    /// The command of every step for the environment with the variables of the runbook substituted,
+    /// a variable that is only captured while the runbook runs is left as it is
+    fn known_commands(&self, environment: &Environment) -> Vec<Vec<String>> {
+        let variables = self.variables_for(environment);
+        self.steps
+            .iter()
+            .filter(|step| !step.command.is_empty())
+            .map(|step| {
+                step.command
+                    .iter()
+                    .map(|part| substitute_known_variables(part, &variables))
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn variables_for(&self, environment: &Environment) -> BTreeMap<String, String> {
+        let mut variables = self.variables.clone();
+        variables.insert(String::from("env"), environment.name.clone());
+        variables
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if self.steps.is_empty() {
+            bail!("runbook has no steps");
+        }
+        for (index, step) in self.steps.iter().enumerate() {
+            let number = index + 1;
+            if step.command.is_empty() == step.sleep.is_none() {
+                bail!("step {} needs either a command or a sleep", number);
+            }
+            if step.sleep.is_some() && !step.capture.is_empty() {
+                bail!("step {} can not capture variables from a sleep", number);
+            }
+            for (variable, regex) in &step.capture {
+                Regex::new(regex).with_context(|| {
+                    format!(
+                        "step {} has an invalid regex for variable {:?}",
+                        number, variable
+                    )
+                })?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Result of the runbook for one environment
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct RunbookOutcome {
+    pub env_name: String,
+    /// Description of the steps that failed, the last one stopped the runbook unless it had
+    /// continue_on_error
+    pub failed_steps: Vec<String>,
+    pub completed: bool,
+}
+
+/// The protected environments that a step of the runbook would change, with the first command that
+/// changes them. A command that starts with a variable that is only captured while the runbook runs
+/// is not known, so it may change the environment as well
+pub fn protected_environments<'a>(
+    settings: &Settings,
+    environments: &'a [Environment],
+    runbook: &Runbook,
+) -> Result<Vec<(&'a Environment, Vec<String>)>> {
+    let mut result = Vec::new();
+    for environment in environments.iter().filter(|env| env.protected) {
+        for command in runbook.known_commands(environment) {
+            let environment = std::slice::from_ref(environment);
+            if !protection::protected_environments(settings, environment, &command)?.is_empty() {
+                result.push((&environment[0], command));
+                break;
+            }
+        }
+    }
+    Ok(result)
+}
+
+/// Steps are executed on all environments at once, so an interactive command would wait for the
+/// terminal forever
+fn check_if_not_interactive(settings: &Settings, command: &[String]) -> Result<()> {
+    if classify(settings, &expand(settings, command)?).interactive {
+        bail!(
+            "{:?} is interactive and can not run in a runbook, add a flag that makes it non-interactive (for example \"-f\")",
+            command.join(" ")
+        );
+    }
+    Ok(())
+}
+
+/// Execute the runbook for every environment in parallel, the steps of one environment are
+/// executed in order. Fails before anything is executed when a step is interactive
+pub async fn run(
+    settings: &Settings,
+    options: Arc<Options>,
+    names: &str,
+    runbook: Arc<Runbook>,
+    original_cf_home: Arc<PathBuf>,
+    mcf_folder: Arc<PathBuf>,
+) -> Result<Vec<RunbookOutcome>> {
+    runbook.validate()?;
+    let environments = known_environments(names, settings)?;
+    for environment in &environments {
+        for command in runbook.known_commands(environment) {
+            check_if_not_interactive(settings, &command)?;
+        }
+    }
+    let max_chars = max_environment_name_length(&environments)?;
+    let settings = Arc::new(settings.clone());
+    let mut tasks: JoinSet<Result<RunbookOutcome>> = JoinSet::new();
+    for environment in environments {
+        let options = options.clone();
+        let runbook = runbook.clone();
+        let settings = settings.clone();
+        let original_cf_home = original_cf_home.clone();
+        let mcf_folder = mcf_folder.clone();
+        tasks.spawn(async move {
+            run_environment(
+                &settings,
+                options,
+                &environment,
+                &runbook,
+                original_cf_home,
+                mcf_folder,
+                line_prefix(&environment.name, max_chars),
+            )
+            .await
+        });
+    }
+    let mut outcomes = Vec::new();
+    while let Some(result) = tasks.join_next().await {
+        outcomes.push(result??);
+    }
+    outcomes.sort_by(|a, b| a.env_name.cmp(&b.env_name));
+    Ok(outcomes)
+}
+
+async fn run_environment(
+    settings: &Settings,
+    options: Arc<Options>,
+    environment: &Environment,
+    runbook: &Runbook,
+    original_cf_home: Arc<PathBuf>,
+    mcf_folder: Arc<PathBuf>,
+    prefix: String,
+) -> Result<RunbookOutcome> {
+    let mut variables = runbook.variables_for(environment);
+    let mut outcome = RunbookOutcome {
+        env_name: environment.name.clone(),
+        failed_steps: Vec::new(),
+        completed: true,
+    };
+    let total = runbook.steps.len();
+    for (index, step) in runbook.steps.iter().enumerate() {
+        println!(
+            "{}==> step {}/{}: {}",
+            prefix,
+            index + 1,
+            total,
+            step.description()
+        );
+        let result = match step.sleep {
+            Some(some) => {
+                tokio::time::sleep(Duration::from_secs(some)).await;
+                Ok(())
+            }
+            None => {
+                run_step(
+                    settings,
+                    options.clone(),
+                    environment,
+                    step,
+                    &mut variables,
+                    original_cf_home.clone(),
+                    mcf_folder.clone(),
+                    &prefix,
+                )
+                .await
+            }
+        };
+        if let Err(error) = result {
+            println!("{}==> step {} failed: {:#}", prefix, index + 1, error);
+            outcome.failed_steps.push(step.description());
+            if !step.continue_on_error {
+                outcome.completed = false;
+                break;
+            }
+        }
+    }
+    Ok(outcome)
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn run_step(
+    settings: &Settings,
+    options: Arc<Options>,
+    environment: &Environment,
+    step: &Step,
+    variables: &mut BTreeMap<String, String>,
+    original_cf_home: Arc<PathBuf>,
+    mcf_folder: Arc<PathBuf>,
+    prefix: &str,
+) -> Result<()> {
+    let command = step
+        .command
+        .iter()
+        .map(|part| substitute_variables(part, variables))
+        .collect::<Result<Vec<String>>>()?;
+    // a captured variable can make the command interactive
+    check_if_not_interactive(settings, &command)?;
+    let command = Arc::new(expand(settings, &command)?);
+    let mut child = child_tokio(
+        options,
+        command,
+        environment,
+        original_cf_home,
+        mcf_folder,
+        &false,
+    )?;
+    let stdout = child.stdout.take().context("runbook: no stdout")?;
+    let mut lines = BufReader::new(stdout).lines();
+    let mut output = String::new();
+    while let Some(line) = lines.next_line().await? {
+        println!("{}{}", prefix, line);
+        output.push_str(&line);
+        output.push('\n');
+    }
+    let status = child.wait().await?;
+    if !status.success() {
+        bail!(McfError::NonZeroExit {
+            failures: vec![(environment.name.clone(), status)],
+        });
+    }
+    for (variable, regex) in &step.capture {
+        let value = capture(regex, &output)?
+            .with_context(|| format!("could not capture {:?} from the output", variable))?;
+        variables.insert(variable.clone(), value);
+    }
+    Ok(())
+}
+
+fn capture(regex: &str, output: &str) -> Result<Option<String>> {
+    let captures = Regex::new(regex)?.captures(output);
+    Ok(captures.and_then(|captures| {
+        captures
+            .get(1)
+            .or_else(|| captures.get(0))
+            .map(|value| value.as_str().to_string())
+    }))
+}
+
+/// Replace ${name} with the value of the variable, an unknown variable is left as it is
+fn substitute_known_variables(value: &str, variables: &BTreeMap<String, String>) -> String {
+    let mut result = String::new();
+    let mut rest = value;
+    while let Some(start) = rest.find("${") {
+        result.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        match after
+            .find('}')
+            .and_then(|end| Some((end, variables.get(&after[..end])?)))
+        {
+            Some((end, variable)) => {
+                result.push_str(variable);
+                rest = &after[end + 1..];
+            }
+            None => {
+                result.push_str("${");
+                rest = after;
+            }
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
+/// Replace ${name} with the value of the variable, an unknown variable is an error
+fn substitute_variables(value: &str, variables: &BTreeMap<String, String>) -> Result<String> {
+    let mut result = String::new();
+    let mut rest = value;
+    while let Some(start) = rest.find("${") {
+        result.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let end = after
+            .find('}')
+            .with_context(|| format!("missing '}}' in {:?}", value))?;
+        let name = &after[..end];
+        let variable = variables
+            .get(name)
+            .with_context(|| format!("unknown variable {:?} in {:?}", name, value))?;
+        result.push_str(variable);
+        rest = &after[end + 1..];
+    }
+    result.push_str(rest);
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use gag::BufferRedirect;
+    use tempfile::tempdir;
+
+    use super::*;
+
+    fn strings(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+
+    fn settings() -> Settings {
+        Settings {
+            environments: vec![Environment {
+                name: "p01".to_string(),
+                url: "url".to_string(),
+                ..Default::default()
+            }],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_substitute_variables() {
+        let variables = BTreeMap::from([
+            (String::from("app"), String::from("my-app")),
+            (String::from("env"), String::from("p01")),
+        ]);
+        assert_eq!(
+            substitute_variables("${app}-${env}", &variables).unwrap(),
+            "my-app-p01"
+        );
+        assert_eq!(substitute_variables("$1", &variables).unwrap(), "$1");
+        assert!(substitute_variables("${unknown}", &variables).is_err());
+        assert!(substitute_variables("${app", &variables).is_err());
+    }
+
+    #[test]
+    fn test_substitute_known_variables() {
+        let variables = BTreeMap::from([(String::from("action"), String::from("delete"))]);
+        assert_eq!(
+            substitute_known_variables("${action}-${guid}", &variables),
+            "delete-${guid}"
+        );
+        assert_eq!(
+            substitute_known_variables("${action", &variables),
+            "${action"
+        );
+    }
+
+    #[test]
+    fn test_protected_environments_after_substitution() {
+        let environments = vec![
+            Environment {
+                name: String::from("dev"),
+                ..Default::default()
+            },
+            Environment {
+                name: String::from("prod"),
+                protected: true,
+                ..Default::default()
+            },
+        ];
+        let runbook = |variables: &[(&str, &str)], command: &[&str]| Runbook {
+            variables: variables
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect(),
+            steps: vec![
+                Step {
+                    command: strings(&["apps"]),
+                    ..Default::default()
+                },
+                Step {
+                    command: strings(command),
+                    ..Default::default()
+                },
+            ],
+        };
+        let settings = Settings::default();
+        let result = protected_environments(
+            &settings,
+            &environments,
+            &runbook(&[("action", "delete")], &["${action}", "my-app", "-f"]),
+        )
+        .unwrap();
+        assert_eq!(result.len(), 1);
+        assert_eq!(result[0].0.name, "prod");
+        assert_eq!(result[0].1, strings(&["delete", "my-app", "-f"]));
+        let captured = runbook(&[], &["${captured}", "my-app"]);
+        assert_eq!(
+            protected_environments(&settings, &environments, &captured)
+                .unwrap()
+                .len(),
+            1
+        );
+        let read_only = runbook(&[("action", "app")], &["${action}", "my-app"]);
+        assert!(protected_environments(&settings, &environments, &read_only)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_run_refuses_interactive_steps() {
+        let tempdir: PathBuf = tempdir().unwrap().keep();
+        let runbook = Runbook {
+            variables: BTreeMap::from([(String::from("action"), String::from("delete"))]),
+            steps: vec![Step {
+                command: strings(&["${action}", "my-app"]),
+                ..Default::default()
+            }],
+        };
+        let result = run(
+            &settings(),
+            Arc::new(Options {
+                cf_binary_name: String::from("echo"),
+                mcf_home: tempdir.to_str().unwrap().to_string(),
+            }),
+            "p01",
+            Arc::new(runbook),
+            Arc::new(tempdir.join(".cf")),
+            Arc::new(tempdir.join("mcf")),
+        )
+        .await;
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .starts_with("\"delete my-app\" is interactive and can not run in a runbook"));
+        assert!(!tempdir.join("mcf").exists());
+    }
+
+    #[test]
+    fn test_capture() {
+        assert_eq!(
+            capture(r"guid:\s+(\S+)", "name: app\nguid:   1234-abcd\n").unwrap(),
+            Some(String::from("1234-abcd"))
+        );
+        assert_eq!(
+            capture(r"\d+", "instances: 3").unwrap(),
+            Some(String::from("3"))
+        );
+        assert_eq!(capture(r"guid", "name: app").unwrap(), None);
+    }
+
+    #[test]
+    fn test_validate() {
+        let runbook = Runbook {
+            steps: vec![Step {
+                command: strings(&["apps"]),
+                sleep: Some(1),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        assert_eq!(
+            runbook.validate().unwrap_err().to_string(),
+            "step 1 needs either a command or a sleep"
+        );
+        let runbook = Runbook {
+            steps: vec![Step {
+                command: strings(&["app", "x"]),
+                capture: BTreeMap::from([(String::from("guid"), String::from("(unclosed"))]),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        assert!(runbook.validate().is_err());
+        assert!(Runbook::default().validate().is_err());
+    }
+
+    #[test]
+    fn test_load() {
+        let tempdir: PathBuf = tempdir().unwrap().keep();
+        let path = tempdir.join("runbook.yml");
+        fs::write(
+            &path,
+            "variables:\n  app: my-app\nsteps:\n- command: [stop, \"${app}\"]\n- sleep: 5\n  continue_on_error: true\n",
+        )
+        .unwrap();
+        let runbook = Runbook::load(&path).unwrap();
+        assert_eq!(runbook.steps.len(), 2);
+        assert_eq!(runbook.steps[0].command, strings(&["stop", "${app}"]));
+        assert_eq!(runbook.steps[1].sleep, Some(5));
+        assert!(runbook.steps[1].continue_on_error);
+    }
+
+    #[tokio::test]
+    async fn test_run() {
+        let _stdout = crate::STDOUT_REDIRECT.lock().await;
+        test_run_captures_variables_from_previous_step().await;
+        test_run_stops_on_error_unless_continue_on_error().await;
+    }
+
+    async fn test_run_captures_variables_from_previous_step() {
+        let mut buf = BufferRedirect::stdout().unwrap();
+        let tempdir: PathBuf = tempdir().unwrap().keep();
+        let _ = std::fs::create_dir_all(tempdir.join(".cf").join("plugins"));
+        let runbook = Runbook {
+            variables: BTreeMap::from([(String::from("app"), String::from("my-app"))]),
+            steps: vec![
+                Step {
+                    command: strings(&["guid:", "${app}-guid"]),
+                    capture: BTreeMap::from([(
+                        String::from("guid"),
+                        String::from(r"guid:\s+(\S+)"),
+                    )]),
+                    ..Default::default()
+                },
+                Step {
+                    command: strings(&["captured", "${guid}", "on", "${env}"]),
+                    ..Default::default()
+                },
+            ],
+        };
+        let result = run(
+            &settings(),
+            Arc::new(Options {
+                cf_binary_name: String::from("echo"),
+                mcf_home: tempdir.to_str().unwrap().to_string(),
+            }),
+            "p01",
+            Arc::new(runbook),
+            Arc::new(tempdir.join(".cf")),
+            Arc::new(tempdir.join("mcf")),
+        )
+        .await;
+        let mut output = String::new();
+        buf.read_to_string(&mut output).unwrap();
+        drop(buf);
+        assert_eq!(
+            result.unwrap(),
+            vec![RunbookOutcome {
+                env_name: String::from("p01"),
+                failed_steps: Vec::new(),
+                completed: true,
+            }]
+        );
+        assert!(output.contains("p01 | ==> step 2/2: captured ${guid} on ${env}\n"));
+        assert!(output.contains("p01 | captured my-app-guid on p01\n"));
+    }
+
+    async fn test_run_stops_on_error_unless_continue_on_error() {
+        let buf = BufferRedirect::stdout().unwrap();
+        let tempdir: PathBuf = tempdir().unwrap().keep();
+        let _ = std::fs::create_dir_all(tempdir.join(".cf").join("plugins"));
+        let runbook = Runbook {
+            steps: vec![
+                Step {
+                    name: Some(String::from("first")),
+                    command: strings(&["first"]),
+                    continue_on_error: true,
+                    ..Default::default()
+                },
+                Step {
+                    name: Some(String::from("second")),
+                    command: strings(&["second"]),
+                    ..Default::default()
+                },
+                Step {
+                    name: Some(String::from("third")),
+                    command: strings(&["third"]),
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        };
+        let result = run(
+            &settings(),
+            Arc::new(Options {
+                cf_binary_name: String::from("false"),
+                mcf_home: tempdir.to_str().unwrap().to_string(),
+            }),
+            "p01",
+            Arc::new(runbook),
+            Arc::new(tempdir.join(".cf")),
+            Arc::new(tempdir.join("mcf")),
+        )
+        .await;
+        drop(buf);
+        assert_eq!(
+            result.unwrap(),
+            vec![RunbookOutcome {
+                env_name: String::from("p01"),
+                failed_steps: strings(&["first", "second"]),
+                completed: false,
+            }]
+        );
+    }
+}
@@ -1,32 +1,152 @@
+use crate::cf::Version;
+use crate::config::{self, LayerFile};
 use crate::environment::Environment;
+use crate::error::McfError;
+use crate::history::HistorySettings;
 use crate::options::Options;
-use anyhow::{anyhow, Result};
+use crate::subcommand::SubcommandClasses;
+use anyhow::{anyhow, Context, Result};
+use regex::RegexSet;
 use serde::{Deserialize, Serialize};
 use std::{
-    fs::{self, File},
+    collections::BTreeMap,
+    fs::{self, File, OpenOptions},
     io::Write,
-    path::PathBuf,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
 };
 
-#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Default)]
+const MAX_BACKUPS: usize = 10;
+
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone, Default)]
 pub struct Settings {
+    #[serde(default)]
     pub environments: Vec<Environment>,
+    /// Saved commands that can be used in exec instead of a cf command
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub aliases: BTreeMap<String, Vec<String>>,
+    /// Classes of cf subcommands that extend the built-in classes
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub subcommands: BTreeMap<String, SubcommandClasses>,
+    /// Oldest cf cli version that may be used for any environment
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cf_min_version: Option<Version>,
+    /// Regular expressions for transient errors, with patterns "mcf exec --retry" only retries an
+    /// environment when the output of cf matches one of them
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub retry_patterns: Vec<String>,
+    /// How many runs of "mcf exec" the history keeps
+    #[serde(default, skip_serializing_if = "HistorySettings::is_default")]
+    pub history: HistorySettings,
 }
 
 impl Settings {
+    /// Load the merged settings of the system, user and project settings files
     pub fn load(options: &Options) -> Result<Self> {
-        let override_path = options.get_mcf_home_path_buf();
-        let settings_path = path_to_settings_file(override_path);
-        Ok(read_settings_file_from_disk(&settings_path).unwrap_or_default())
+        Settings::load_files(&config::layer_files(options))
     }
 
+    /// Load the merged settings of the given settings files
+    pub fn load_files(files: &[LayerFile]) -> Result<Self> {
+        let mut settings = config::load_files(files)?.settings;
+        settings.add_missing_url_schemes();
+        settings.validate().with_context(|| {
+            format!(
+                "invalid settings, fix one of the settings files {:?} or run \"mcf env restore\"",
+                files
+                    .iter()
+                    .filter(|file| file.path.exists())
+                    .map(|file| &file.path)
+                    .collect::<Vec<&PathBuf>>()
+            )
+        })?;
+        Ok(settings)
+    }
+
+    /// Load only the user settings file, which is the file that mcf writes to
+    pub fn load_user(options: &Options) -> Result<Self> {
+        let settings_path = path_to_settings_file(options.get_mcf_home_path_buf());
+        if !settings_path.exists() {
+            return Ok(Settings::default());
+        }
+        let mut settings = read_settings_file_from_disk(&settings_path)?;
+        settings.add_missing_url_schemes();
+        Ok(settings)
+    }
+
+    /// Only "mcf env add" and "mcf env update" refuse a url without a scheme, existing settings
+    /// files keep working and get the scheme the next time they are saved
+    fn add_missing_url_schemes(&mut self) {
+        self.environments
+            .iter_mut()
+            .for_each(Environment::add_missing_url_scheme);
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        for environment in &self.environments {
+            environment.validate()?;
+        }
+        RegexSet::new(&self.retry_patterns).context("invalid retry_patterns")?;
+        Ok(())
+    }
+
+    /// Save the settings to the user settings file while holding the settings lock
     pub fn save(&self, options: &Options) -> Result<()> {
+        let _lock = lock_settings_file(&options.get_mcf_home_path_buf())?;
+        self.save_locked(options)
+    }
+
+    /// Save the settings, the caller holds the settings lock
+    fn save_locked(&self, options: &Options) -> Result<()> {
         let override_path = options.get_mcf_home_path_buf();
         let settings_path = path_to_settings_file(override_path);
         write_settings_file_to_disk(&settings_path, self)?;
         Ok(())
     }
 
+    /// Load the settings while holding the settings lock, apply `modify` and save the result.
+    /// Use this for every load-modify-save cycle so concurrent mcf invocations do not lose writes.
+    pub fn update<F>(options: &Options, modify: F) -> Result<()>
+    where
+        F: FnOnce(&mut Settings) -> Result<()>,
+    {
+        let _lock = lock_settings_file(&options.get_mcf_home_path_buf())?;
+        let mut settings = Settings::load_user(options)?;
+        modify(&mut settings)?;
+        settings.save_locked(options)
+    }
+
+    pub fn list_backups(options: &Options) -> Result<Vec<String>> {
+        list_backups_on_disk(&path_to_backups_folder(options.get_mcf_home_path_buf()))
+    }
+
+    /// Restore the given backup, or the most recent one when no name is given.
+    /// The current settings are backed up first, so a restore can be rolled back as well.
+    pub fn restore(options: &Options, backup: &Option<String>) -> Result<String> {
+        let _lock = lock_settings_file(&options.get_mcf_home_path_buf())?;
+        let backups_folder = path_to_backups_folder(options.get_mcf_home_path_buf());
+        let backups = list_backups_on_disk(&backups_folder)?;
+        let name = match backup {
+            Some(some) => backups
+                .iter()
+                .find(|backup| *backup == some)
+                .cloned()
+                .with_context(|| {
+                    format!(
+                        "could not find backup {:?}, available backups {:?}",
+                        some, backups
+                    )
+                })?,
+            None => backups
+                .first()
+                .cloned()
+                .context("there are no backups to restore")?,
+        };
+        let restored = read_settings_file_from_disk(&backups_folder.join(&name))?;
+        restored.save_locked(options)?;
+        Ok(name)
+    }
+
     pub fn get_environment_by_name(&self, name: &String) -> Option<Environment> {
         self.environments
             .iter()
@@ -35,24 +155,95 @@ impl Settings {
     }
 }
 
-fn path_to_settings_file(override_path: PathBuf) -> PathBuf {
+pub(crate) fn path_to_settings_file(override_path: PathBuf) -> PathBuf {
     let filename = "settings.yml";
     override_path.join(filename)
 }
 
-fn write_settings_file_to_disk(path: &PathBuf, settings: &Settings) -> Result<()> {
-    fs::create_dir_all(
-        path
-            .parent()
-            .ok_or(anyhow!("Dirs crate didn't provide an parent folder"))?,
+fn path_to_backups_folder(override_path: PathBuf) -> PathBuf {
+    override_path.join("backups")
+}
+
+fn lock_settings_file(mcf_home: &Path) -> Result<File> {
+    fs::create_dir_all(mcf_home)?;
+    let lock_file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(mcf_home.join("settings.lock"))
+        .context("could not open the settings lock file")?;
+    lock_file
+        .lock()
+        .context("could not acquire the settings lock")?;
+    Ok(lock_file)
+}
+
+fn write_settings_file_to_disk(path: &Path, settings: &Settings) -> Result<()> {
+    let parent = path
+        .parent()
+        .ok_or(anyhow!("Dirs crate didn't provide an parent folder"))?;
+    fs::create_dir_all(parent)?;
+    if path.exists() {
+        backup_settings_file(path, &path_to_backups_folder(parent.to_path_buf()))?;
+    }
+    write_atomically(path, &serde_yaml::to_string(settings)?)
+}
+
+/// Write to a temp file next to the file and rename it, so mcf processes that run at the same
+/// time never read half a file. The temp file has the process id, so they never share it either
+pub(crate) fn write_atomically(path: &Path, content: &str) -> Result<()> {
+    let extension = path
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let temp_path = path.with_extension(format!("{}.{}.tmp", extension, std::process::id()));
+    let mut temp_file = File::create(&temp_path)?;
+    temp_file.write_all(content.as_bytes())?;
+    temp_file.sync_all()?;
+    fs::rename(&temp_path, path)?;
+    Ok(())
+}
+
+fn backup_settings_file(path: &Path, backups_folder: &Path) -> Result<()> {
+    fs::create_dir_all(backups_folder)?;
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_nanos();
+    fs::copy(
+        path,
+        backups_folder.join(format!("settings-{:020}.yml", timestamp)),
     )?;
-    File::create(path)?.write_all(serde_yaml::to_string(settings)?.as_bytes())?;
+    for old_backup in list_backups_on_disk(backups_folder)?
+        .iter()
+        .skip(MAX_BACKUPS)
+    {
+        fs::remove_file(backups_folder.join(old_backup))?;
+    }
     Ok(())
 }
 
+/// Backup file names, newest first.
+fn list_backups_on_disk(backups_folder: &Path) -> Result<Vec<String>> {
+    if !backups_folder.exists() {
+        return Ok(Vec::new());
+    }
+    let mut backups = fs::read_dir(backups_folder)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.file_name().to_string_lossy().to_string())
+        .filter(|name| name.starts_with("settings-") && name.ends_with(".yml"))
+        .collect::<Vec<String>>();
+    backups.sort();
+    backups.reverse();
+    Ok(backups)
+}
+
 fn read_settings_file_from_disk(path: &PathBuf) -> Result<Settings> {
     let settings_file_as_string = fs::read_to_string(path)?;
-    let settings_file: Settings = serde_yaml::from_str(settings_file_as_string.as_str())?;
+    let settings_file: Settings =
+        serde_yaml::from_str(settings_file_as_string.as_str()).map_err(|source| {
+            McfError::SettingsParse {
+                path: Some(path.clone()),
+                source,
+            }
+        })?;
     Ok(settings_file)
 }
 
@@ -65,10 +256,19 @@ mod tests {
         let _ = env_logger::builder().is_test(true).try_init();
     }
 
+    /// Load only the user settings file, so a system or project settings file does not change the
+    /// outcome of a test
+    fn load_user_layer(options: &Options) -> Result<Settings> {
+        Settings::load_files(&[LayerFile {
+            layer: config::Layer::User,
+            path: path_to_settings_file(options.get_mcf_home_path_buf()),
+        }])
+    }
+
     #[test]
     fn test_path_to_settings_file() {
         init();
-        let opts = Options::new(None, Some("/test/mcf/".to_string()));
+        let opts = Options::new(None, Some("/test/mcf/".to_string())).unwrap();
         let path = opts.get_mcf_home_path_buf();
         let actual_path = path_to_settings_file(path);
         let expected_path = PathBuf::from("/test/mcf/settings.yml");
@@ -77,16 +277,17 @@ mod tests {
 
     #[test]
     fn test_write_empty_settings_file_to_disk() {
-        let tempdir: PathBuf = tempdir().unwrap().into_path();
+        let tempdir: PathBuf = tempdir().unwrap().keep();
         init();
         let _ = write_settings_file_to_disk(
             &path_to_settings_file(tempdir.clone()),
             &Settings {
                 environments: Vec::new(),
+                ..Default::default()
             },
         );
         assert_eq!(
-            fs::read_to_string(&path_to_settings_file(tempdir)).unwrap(),
+            fs::read_to_string(path_to_settings_file(tempdir)).unwrap(),
             String::from("environments: []\n")
         );
     }
@@ -94,30 +295,32 @@ mod tests {
     #[test]
     fn load_will_return_empty_settings_file_when_there_is_no_file_on_disk() {
         init();
-        let tempdir = tempdir().unwrap().into_path().to_str().unwrap().to_string();
+        let tempdir = tempdir().unwrap().keep().to_str().unwrap().to_string();
 
-        let options = Options::new(None, Some(tempdir));
-        assert_eq!(Settings::load(&options).unwrap(), Settings::default());
+        let options = Options::new(None, Some(tempdir)).unwrap();
+        assert_eq!(load_user_layer(&options).unwrap(), Settings::default());
     }
 
     #[test]
     fn load_will_return_the_settings_file_when_there_is_a_file_on_disk() {
-        let tempdir: PathBuf = tempdir().unwrap().into_path();
+        let tempdir: PathBuf = tempdir().unwrap().keep();
         init();
         let expected = Environment {
             name: "name".to_string(),
-            url: "url".to_string(),
+            url: "https://api.example.com".to_string(),
             sso: false,
             skip_ssl_validation: false,
+            ..Default::default()
         };
         let _ = write_settings_file_to_disk(
             &path_to_settings_file(tempdir.clone()),
             &Settings {
                 environments: vec![expected.clone()],
+                ..Default::default()
             },
         );
         assert_eq!(
-            Settings::load(&Options {
+            load_user_layer(&Options {
                 cf_binary_name: "cf".to_string(),
                 mcf_home: tempdir.to_str().unwrap().to_string()
             })
@@ -132,19 +335,141 @@ mod tests {
         init();
         let option = Options {
             cf_binary_name: "cf".to_string(),
-            mcf_home: tempdir().unwrap().into_path().to_str().unwrap().to_string(),
+            mcf_home: tempdir().unwrap().keep().to_str().unwrap().to_string(),
         };
         let expected = Settings {
             environments: vec![Environment {
                 name: "name".to_string(),
-                url: "url".to_string(),
+                url: "https://api.example.com".to_string(),
                 sso: false,
                 skip_ssl_validation: false,
+                ..Default::default()
             }],
+            ..Default::default()
         };
 
         let result = expected.save(&option);
         assert!(result.is_ok());
-        assert_eq!(Settings::load(&option).unwrap(), expected);
+        assert_eq!(load_user_layer(&option).unwrap(), expected);
+    }
+
+    #[test]
+    fn load_will_fail_when_an_environment_is_invalid() {
+        let tempdir: PathBuf = tempdir().unwrap().keep();
+        let _ = write_settings_file_to_disk(
+            &path_to_settings_file(tempdir.clone()),
+            &Settings {
+                environments: vec![Environment {
+                    name: "../x".to_string(),
+                    url: "https://api.example.com".to_string(),
+                    sso: false,
+                    skip_ssl_validation: false,
+                    ..Default::default()
+                }],
+                ..Default::default()
+            },
+        );
+        let result = load_user_layer(
+            &Options::new(None, Some(tempdir.to_str().unwrap().to_string())).unwrap(),
+        );
+        assert!(result.is_err());
+        assert!(
+            format!("{:#}", result.unwrap_err()).contains("environment name \"../x\" contains '/'")
+        );
+    }
+
+    #[test]
+    fn load_will_add_https_to_a_url_without_a_scheme() {
+        let tempdir: PathBuf = tempdir().unwrap().keep();
+        fs::write(
+            path_to_settings_file(tempdir.clone()),
+            "environments:\n- name: p01\n  url: api.sys.example.com\n  sso: false\n  skip_ssl_validation: false\n",
+        )
+        .unwrap();
+        let options = Options::new(None, Some(tempdir.to_str().unwrap().to_string())).unwrap();
+        let settings = load_user_layer(&options).unwrap();
+        assert_eq!(settings.environments[0].url, "https://api.sys.example.com");
+        crate::environment::update(&options, &String::from("p01"), |environment| {
+            environment.org = Some(String::from("team-org"))
+        })
+        .unwrap();
+        assert!(fs::read_to_string(path_to_settings_file(tempdir))
+            .unwrap()
+            .contains("url: https://api.sys.example.com"));
+    }
+
+    #[test]
+    fn save_will_not_leave_a_temp_file_behind() {
+        let tempdir: PathBuf = tempdir().unwrap().keep();
+        let option = Options::new(None, Some(tempdir.to_str().unwrap().to_string())).unwrap();
+        let result = Settings::default().save(&option);
+        assert!(result.is_ok());
+        assert!(tempdir.join("settings.yml").exists());
+        assert!(!fs::read_dir(&tempdir).unwrap().any(|entry| entry
+            .unwrap()
+            .file_name()
+            .to_string_lossy()
+            .ends_with(".tmp")));
+    }
+
+    #[test]
+    fn save_will_keep_a_limited_number_of_backups() {
+        let tempdir: PathBuf = tempdir().unwrap().keep();
+        let option = Options::new(None, Some(tempdir.to_str().unwrap().to_string())).unwrap();
+        for _ in 0..MAX_BACKUPS + 3 {
+            Settings::default().save(&option).unwrap();
+        }
+        assert_eq!(Settings::list_backups(&option).unwrap().len(), MAX_BACKUPS);
+    }
+
+    #[test]
+    fn update_will_modify_the_settings_on_disk() {
+        let tempdir: PathBuf = tempdir().unwrap().keep();
+        let option = Options::new(None, Some(tempdir.to_str().unwrap().to_string())).unwrap();
+        let result = Settings::update(&option, |settings| {
+            settings.environments.push(Environment {
+                name: "name".to_string(),
+                url: "https://api.example.com".to_string(),
+                sso: false,
+                skip_ssl_validation: false,
+                ..Default::default()
+            });
+            Ok(())
+        });
+        assert!(result.is_ok());
+        assert_eq!(load_user_layer(&option).unwrap().environments.len(), 1);
+    }
+
+    #[test]
+    fn restore_will_roll_back_to_the_latest_backup() {
+        let tempdir: PathBuf = tempdir().unwrap().keep();
+        let option = Options::new(None, Some(tempdir.to_str().unwrap().to_string())).unwrap();
+        let first = Settings {
+            environments: vec![Environment {
+                name: "first".to_string(),
+                url: "https://api.example.com".to_string(),
+                sso: false,
+                skip_ssl_validation: false,
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        first.save(&option).unwrap();
+        Settings::default().save(&option).unwrap();
+        let result = Settings::restore(&option, &None);
+        assert!(result.is_ok());
+        assert_eq!(load_user_layer(&option).unwrap(), first);
+    }
+
+    #[test]
+    fn restore_will_fail_when_there_are_no_backups() {
+        let tempdir: PathBuf = tempdir().unwrap().keep();
+        let option = Options::new(None, Some(tempdir.to_str().unwrap().to_string())).unwrap();
+        let result = Settings::restore(&option, &None);
+        assert!(result.is_err());
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "there are no backups to restore"
+        );
     }
 }
@@ -0,0 +1,343 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use anyhow::{anyhow, bail, Result};
+use rustyline::error::ReadlineError;
+use rustyline::DefaultEditor;
+use strum::{AsRefStr, Display, EnumString};
+
+use crate::alias::expand;
+use crate::exec::{exec, exec_grouped, known_environments};
+use crate::options::Options;
+use crate::protection::confirm_protected_environments;
+use crate::settings::Settings;
+use crate::subcommand::classify;
+
+const HISTORY_FILE_NAME: &str = "shell_history";
+
+const HELP: &str = "Enter a cf command (or alias) to execute it on the active environments, or:
+  :add <names>      add environments (example \"cf-dev,cf-prod\") to the active environments
+  :remove <names>   remove environments from the active environments
+  :envs             show the active environments
+  :mode <mode>      show the output in parallel, sequential or grouped mode
+  :help             show this help
+  :quit             leave the shell (or ctrl+d)
+  ctrl+c            stop the running command and return to the prompt";
+
+/// How the output of the environments is shown
+#[derive(Debug, Clone, Copy, PartialEq, Eq, AsRefStr, Display, EnumString)]
+#[strum(serialize_all = "lowercase")]
+pub enum OutputMode {
+    /// Prefixed lines as soon as they arrive
+    Parallel,
+    /// One environment after the other
+    Sequential,
+    /// All environments at once, printed per environment when all are done
+    Grouped,
+}
+
+/// What the shell should do with an entered line
+#[derive(Debug, PartialEq, Eq)]
+pub enum Action {
+    Execute(Vec<String>),
+    Print(String),
+    Quit,
+    Nothing,
+}
+
+/// The active environments and output mode of the shell
+#[derive(Debug)]
+pub struct Shell {
+    pub environments: Vec<String>,
+    pub mode: OutputMode,
+}
+
+impl Shell {
+    pub fn new(settings: &Settings, names: &str) -> Result<Shell> {
+        Ok(Shell {
+            environments: known_environments(names, settings)?
+                .into_iter()
+                .map(|env| env.name)
+                .collect(),
+            mode: OutputMode::Parallel,
+        })
+    }
+
+    pub fn prompt(&self) -> String {
+        format!("mcf [{}] ({})> ", self.environments.join(","), self.mode)
+    }
+
+    /// The comma separated names of the active environments
+    pub fn names(&self) -> String {
+        self.environments.join(",")
+    }
+
+    /// Handle the built-in commands, any other line is a command to execute
+    pub fn handle_line(&mut self, settings: &Settings, line: &str) -> Result<Action> {
+        let words = split_words(line)?;
+        let Some(first) = words.first() else {
+            return Ok(Action::Nothing);
+        };
+        let argument = words.get(1).map(String::as_str).unwrap_or_default();
+        match first.as_str() {
+            ":add" => {
+                for environment in known_environments(argument, settings)? {
+                    if !self.environments.contains(&environment.name) {
+                        self.environments.push(environment.name);
+                    }
+                }
+                Ok(Action::Print(format!(
+                    "mcf: active environments {}",
+                    self.names()
+                )))
+            }
+            ":remove" => {
+                let remove = argument.split(',').collect::<Vec<&str>>();
+                let remaining = self
+                    .environments
+                    .iter()
+                    .filter(|name| !remove.contains(&name.as_str()))
+                    .cloned()
+                    .collect::<Vec<String>>();
+                if remaining.is_empty() {
+                    bail!("mcf: at least one environment has to stay active");
+                }
+                self.environments = remaining;
+                Ok(Action::Print(format!(
+                    "mcf: active environments {}",
+                    self.names()
+                )))
+            }
+            ":envs" => Ok(Action::Print(format!(
+                "mcf: active environments {}",
+                self.names()
+            ))),
+            ":mode" => match argument.parse::<OutputMode>() {
+                Ok(some) => {
+                    self.mode = some;
+                    Ok(Action::Print(format!("mcf: output mode {}", self.mode)))
+                }
+                Err(_) => bail!(
+                    "mcf: unknown output mode {:?}, use parallel, sequential or grouped",
+                    argument
+                ),
+            },
+            ":help" => Ok(Action::Print(String::from(HELP))),
+            ":quit" | ":exit" => Ok(Action::Quit),
+            builtin if builtin.starts_with(':') => {
+                bail!("mcf: unknown command {:?}, see :help", builtin)
+            }
+            _ => Ok(Action::Execute(words)),
+        }
+    }
+}
+
+/// Split a line into words like a shell does, quotes group words and a backslash escapes the next
+/// character
+pub fn split_words(line: &str) -> Result<Vec<String>> {
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut quote: Option<char> = None;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some(some), c) if c == some => quote = None,
+            (Some('"') | None, '\\') => {
+                if let Some(escaped) = chars.next() {
+                    word.get_or_insert_with(String::new).push(escaped);
+                }
+            }
+            (Some(_), c) => word.get_or_insert_with(String::new).push(c),
+            (None, '\'' | '"') => {
+                quote = Some(c);
+                word.get_or_insert_with(String::new);
+            }
+            (None, c) if c.is_whitespace() => words.extend(word.take()),
+            (None, c) => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    if quote.is_some() {
+        bail!("mcf: unclosed quote in {:?}", line);
+    }
+    words.extend(word);
+    Ok(words)
+}
+
+/// Read commands with line editing and history and execute them on the active environments until
+/// the shell is left
+pub async fn shell(
+    settings: &Settings,
+    options: Arc<Options>,
+    names: &str,
+    original_cf_home: Arc<PathBuf>,
+    mcf_folder: Arc<PathBuf>,
+    yes_i_mean_prod: &bool,
+) -> Result<()> {
+    let mut shell = Shell::new(settings, names)?;
+    let mut editor = DefaultEditor::new()?;
+    let history = options.get_mcf_home_path_buf().join(HISTORY_FILE_NAME);
+    // there is no history the first time
+    let _ = editor.load_history(&history);
+    println!("{}", HELP);
+    loop {
+        let line = match tokio::task::block_in_place(|| editor.readline(&shell.prompt())) {
+            Ok(some) => some,
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(error) => return Err(error.into()),
+        };
+        if !line.trim().is_empty() {
+            editor.add_history_entry(line.as_str())?;
+        }
+        let result = match shell.handle_line(settings, &line) {
+            Ok(Action::Execute(command)) => {
+                tokio::select! {
+                    result = execute(
+                        settings,
+                        &shell,
+                        options.clone(),
+                        command,
+                        original_cf_home.clone(),
+                        mcf_folder.clone(),
+                        yes_i_mean_prod,
+                    ) => result,
+                    // dropping the execution kills cf on every environment, the shell keeps running
+                    _ = tokio::signal::ctrl_c() => Err(anyhow!("mcf: interrupted")),
+                }
+            }
+            Ok(Action::Print(some)) => {
+                println!("{}", some);
+                Ok(())
+            }
+            Ok(Action::Quit) => break,
+            Ok(Action::Nothing) => Ok(()),
+            Err(error) => Err(error),
+        };
+        if let Err(error) = result {
+            eprintln!("{:#}", error);
+        }
+    }
+    std::fs::create_dir_all(options.get_mcf_home_path_buf())?;
+    editor.save_history(&history)?;
+    Ok(())
+}
+
+async fn execute(
+    settings: &Settings,
+    shell: &Shell,
+    options: Arc<Options>,
+    command: Vec<String>,
+    original_cf_home: Arc<PathBuf>,
+    mcf_folder: Arc<PathBuf>,
+    yes_i_mean_prod: &bool,
+) -> Result<()> {
+    let names = shell.names();
+    let environments = known_environments(&names, settings)?;
+    confirm_protected_environments(settings, &environments, &command, yes_i_mean_prod)?;
+    // interactive commands need the terminal and streaming commands never end, so they are not
+    // grouped
+    let classification = classify(settings, &expand(settings, &command)?);
+    let grouped = shell.mode == OutputMode::Grouped
+        && !classification.requires_sequential_mode()
+        && !classification.streaming;
+    let command = Arc::new(command);
+    match shell.mode {
+        OutputMode::Grouped if grouped => {
+            exec_grouped(
+                settings,
+                options,
+                &names,
+                command,
+                original_cf_home,
+                mcf_folder,
+            )
+            .await
+        }
+        _ => {
+            exec(
+                settings,
+                options,
+                &names,
+                command,
+                original_cf_home,
+                mcf_folder,
+                &(shell.mode == OutputMode::Sequential),
+            )
+            .await
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::environment::Environment;
+
+    fn settings() -> Settings {
+        Settings {
+            environments: ["p01", "p02", "p03"]
+                .iter()
+                .map(|name| Environment {
+                    name: name.to_string(),
+                    url: String::from("https://api.example.com"),
+                    ..Default::default()
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    fn strings(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+
+    #[test]
+    fn test_split_words() {
+        assert_eq!(
+            split_words(r#"curl "/v3/apps?names=a b" -H 'X: "y"' \"z"#).unwrap(),
+            strings(&["curl", "/v3/apps?names=a b", "-H", "X: \"y\"", "\"z"])
+        );
+        assert_eq!(split_words("  ").unwrap(), Vec::<String>::new());
+        assert_eq!(split_words("set-env app KEY ''").unwrap().len(), 4);
+        assert!(split_words("logs 'app").is_err());
+    }
+
+    #[test]
+    fn test_add_and_remove_environments() {
+        let settings = settings();
+        let mut shell = Shell::new(&settings, "p01").unwrap();
+        shell.handle_line(&settings, ":add p02,p03,p01").unwrap();
+        assert_eq!(shell.environments, strings(&["p01", "p02", "p03"]));
+        shell.handle_line(&settings, ":remove p01,p03").unwrap();
+        assert_eq!(shell.environments, strings(&["p02"]));
+        assert_eq!(shell.prompt(), "mcf [p02] (parallel)> ");
+        assert!(shell.handle_line(&settings, ":remove p02").is_err());
+        assert!(shell.handle_line(&settings, ":add p04").is_err());
+        assert_eq!(shell.environments, strings(&["p02"]));
+    }
+
+    #[test]
+    fn test_mode() {
+        let settings = settings();
+        let mut shell = Shell::new(&settings, "p01").unwrap();
+        shell.handle_line(&settings, ":mode grouped").unwrap();
+        assert_eq!(shell.mode, OutputMode::Grouped);
+        assert!(shell.handle_line(&settings, ":mode fast").is_err());
+        assert_eq!(shell.mode, OutputMode::Grouped);
+    }
+
+    #[test]
+    fn test_handle_line() {
+        let settings = settings();
+        let mut shell = Shell::new(&settings, "p01,p02").unwrap();
+        assert_eq!(
+            shell
+                .handle_line(&settings, "logs my-app --recent")
+                .unwrap(),
+            Action::Execute(strings(&["logs", "my-app", "--recent"]))
+        );
+        assert_eq!(shell.handle_line(&settings, "").unwrap(), Action::Nothing);
+        assert_eq!(shell.handle_line(&settings, ":quit").unwrap(), Action::Quit);
+        assert!(shell.handle_line(&settings, ":unknown").is_err());
+    }
+}
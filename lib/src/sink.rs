@@ -0,0 +1,286 @@
+use std::collections::BTreeMap;
+use std::process::ExitStatus;
+use std::time::Duration;
+
+use tokio::sync::mpsc;
+
+use crate::exec::{line_prefix, OutputLine};
+
+/// Receives what happens while a command is executed on the environments. When cf uses the
+/// terminal itself (an interactive command in sequential mode), only the start and the exit of
+/// every environment are received.
+pub trait OutputSink: Send {
+    /// The environments the command is executed on, before anything is executed
+    fn begin(&mut self, env_names: &[String], sequential: bool) {
+        let _ = (env_names, sequential);
+    }
+
+    /// cf is started for an environment
+    fn start(&mut self, env_name: &str) {
+        let _ = env_name;
+    }
+
+    /// cf uses the terminal for an environment, so no lines are received for it
+    fn terminal(&mut self, env_name: &str) {
+        let _ = env_name;
+    }
+
+    /// A line of the output of cf for an environment
+    fn line(&mut self, line: OutputLine);
+
+    /// cf exited with an error for an environment and is executed again after the delay, the
+    /// lines of the retry have its attempt
+    fn retry(&mut self, env_name: &str, attempt: u32, status: ExitStatus, delay: Duration) {
+        let _ = (env_name, attempt, status, delay);
+    }
+
+    /// cf is done for an environment
+    fn exit(&mut self, env_name: &str, status: ExitStatus) {
+        let _ = (env_name, status);
+    }
+
+    /// cf could not be executed for an environment, there is no exit status for it
+    fn error(&mut self, env_name: &str, error: &anyhow::Error) {
+        let _ = (env_name, error);
+    }
+
+    /// All environments are done
+    fn end(&mut self) {}
+}
+
+/// Prints the output to the terminal, every line is prefixed with its environment or, when
+/// grouped, the output is printed per environment when all environments are done
+#[derive(Debug, Default)]
+pub struct TerminalSink {
+    grouped: bool,
+    sequential: bool,
+    env_names: Vec<String>,
+    max_chars: usize,
+    lines: Vec<OutputLine>,
+    retries: BTreeMap<String, u32>,
+    exits: BTreeMap<String, ExitStatus>,
+}
+
+impl TerminalSink {
+    pub fn new() -> TerminalSink {
+        TerminalSink::default()
+    }
+
+    pub fn grouped() -> TerminalSink {
+        TerminalSink {
+            grouped: true,
+            ..Default::default()
+        }
+    }
+
+    fn print(line: &OutputLine, prefix: &str) {
+        let label = retry_label(line.attempt);
+        if line.stderr {
+            eprintln!("{}{}{}", prefix, label, line.line);
+        } else {
+            println!("{}{}{}", prefix, label, line.line);
+        }
+    }
+
+    fn prefix(&self, env_name: &str) -> String {
+        if self.sequential {
+            String::new()
+        } else {
+            line_prefix(env_name, self.max_chars)
+        }
+    }
+
+    /// A line for every environment that was retried
+    fn print_retry_summary(&self) {
+        for (env_name, retries) in &self.retries {
+            let outcome = match self.exits.get(env_name) {
+                Some(status) if status.success() => String::from("succeeded"),
+                Some(status) => format!("failed with {}", status),
+                None => String::from("did not finish"),
+            };
+            println!(
+                "mcf: {} {} after {} {}",
+                env_name,
+                outcome,
+                retries,
+                if *retries == 1 { "retry" } else { "retries" }
+            );
+        }
+    }
+}
+
+/// The label in front of the lines of a retry
+pub(crate) fn retry_label(attempt: u32) -> String {
+    if attempt == 0 {
+        String::new()
+    } else {
+        format!("[retry {}] ", attempt)
+    }
+}
+
+impl OutputSink for TerminalSink {
+    fn begin(&mut self, env_names: &[String], sequential: bool) {
+        self.env_names = env_names.to_vec();
+        self.sequential = sequential;
+        self.max_chars = env_names.iter().map(String::len).max().unwrap_or_default();
+    }
+
+    fn start(&mut self, env_name: &str) {
+        if self.sequential {
+            println!(
+                "------------------ NOW ENVIRONMENT {} ------------------",
+                env_name
+            );
+        }
+    }
+
+    fn line(&mut self, line: OutputLine) {
+        if self.grouped {
+            self.lines.push(line);
+        } else {
+            TerminalSink::print(&line, &self.prefix(&line.env_name));
+        }
+    }
+
+    fn retry(&mut self, env_name: &str, attempt: u32, status: ExitStatus, delay: Duration) {
+        self.retries.insert(env_name.to_string(), attempt);
+        eprintln!(
+            "{}mcf: cf exited with {}, retry {} in {}s",
+            self.prefix(env_name),
+            status,
+            attempt,
+            delay.as_secs()
+        );
+    }
+
+    fn exit(&mut self, env_name: &str, status: ExitStatus) {
+        self.exits.insert(env_name.to_string(), status);
+    }
+
+    fn error(&mut self, env_name: &str, error: &anyhow::Error) {
+        eprintln!("{}mcf: {:#}", self.prefix(env_name), error);
+    }
+
+    fn end(&mut self) {
+        if self.grouped && !self.sequential {
+            for env_name in &self.env_names {
+                println!(
+                    "------------------ ENVIRONMENT {} ------------------",
+                    env_name
+                );
+                for line in self.lines.iter().filter(|line| &line.env_name == env_name) {
+                    TerminalSink::print(line, "");
+                }
+            }
+        }
+        self.print_retry_summary();
+    }
+}
+
+/// Keeps the output, the exit statuses and the errors in memory
+#[derive(Debug, Default)]
+pub struct CollectingSink {
+    pub lines: Vec<OutputLine>,
+    pub exits: Vec<(String, ExitStatus)>,
+    /// The environments on which cf could not be executed, with the error
+    pub errors: Vec<(String, String)>,
+}
+
+impl CollectingSink {
+    pub fn new() -> CollectingSink {
+        CollectingSink::default()
+    }
+
+    /// The lines of stdout and stderr of an environment
+    pub fn output(&self, env_name: &str) -> Vec<&str> {
+        self.lines
+            .iter()
+            .filter(|line| line.env_name == env_name)
+            .map(|line| line.line.as_str())
+            .collect()
+    }
+
+    /// The exit status of an environment, there is none when cf could not be executed
+    pub fn status(&self, env_name: &str) -> Option<ExitStatus> {
+        self.exits
+            .iter()
+            .find(|(name, _)| name == env_name)
+            .map(|(_, status)| *status)
+    }
+}
+
+impl OutputSink for CollectingSink {
+    fn line(&mut self, line: OutputLine) {
+        self.lines.push(line);
+    }
+
+    fn exit(&mut self, env_name: &str, status: ExitStatus) {
+        self.exits.push((env_name.to_string(), status));
+    }
+
+    fn error(&mut self, env_name: &str, error: &anyhow::Error) {
+        self.errors
+            .push((env_name.to_string(), format!("{:#}", error)));
+    }
+}
+
+/// What a channel sink sends
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OutputEvent {
+    Line(OutputLine),
+    Retry {
+        env_name: String,
+        attempt: u32,
+        status: ExitStatus,
+    },
+    Exit {
+        env_name: String,
+        status: ExitStatus,
+    },
+    Error {
+        env_name: String,
+        message: String,
+    },
+}
+
+/// Sends the output and the exit statuses to a channel, for example to process them in another
+/// task while cf is still running
+#[derive(Debug)]
+pub struct ChannelSink {
+    sender: mpsc::UnboundedSender<OutputEvent>,
+}
+
+impl ChannelSink {
+    pub fn new(sender: mpsc::UnboundedSender<OutputEvent>) -> ChannelSink {
+        ChannelSink { sender }
+    }
+}
+
+impl OutputSink for ChannelSink {
+    fn line(&mut self, line: OutputLine) {
+        // a receiver that is gone is not interested in the output anymore
+        let _ = self.sender.send(OutputEvent::Line(line));
+    }
+
+    fn retry(&mut self, env_name: &str, attempt: u32, status: ExitStatus, _delay: Duration) {
+        let _ = self.sender.send(OutputEvent::Retry {
+            env_name: env_name.to_string(),
+            attempt,
+            status,
+        });
+    }
+
+    fn exit(&mut self, env_name: &str, status: ExitStatus) {
+        let _ = self.sender.send(OutputEvent::Exit {
+            env_name: env_name.to_string(),
+            status,
+        });
+    }
+
+    fn error(&mut self, env_name: &str, error: &anyhow::Error) {
+        let _ = self.sender.send(OutputEvent::Error {
+            env_name: env_name.to_string(),
+            message: format!("{:#}", error),
+        });
+    }
+}
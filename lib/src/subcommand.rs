@@ -0,0 +1,491 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use strum::{AsRefStr, Display};
+
+use crate::settings::Settings;
+
+/// What a cf subcommand does, a subcommand can have multiple classes
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, AsRefStr, Display,
+)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum Class {
+    /// Reads from the terminal (for example a confirmation prompt or a shell)
+    Interactive,
+    /// Changes something on the environment
+    Mutating,
+    /// Keeps printing output until it is stopped
+    Streaming,
+}
+
+/// The classes of a cf subcommand, this is also the format of the subcommands in the settings
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone, Default)]
+pub struct SubcommandClasses {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub aliases: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub classes: Vec<Class>,
+    /// Flags that make an interactive subcommand non-interactive (for example "-f" for delete)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub not_interactive_with: Vec<String>,
+    /// Flags that make a streaming subcommand stop by itself (for example "--recent" for logs)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub not_streaming_with: Vec<String>,
+    /// Flags that make a subcommand mutating, a flag can include its value (for example "-X POST"
+    /// for curl)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub mutating_with: Vec<String>,
+}
+
+impl SubcommandClasses {
+    /// Add the aliases, classes and flags of the other subcommand, nothing is removed
+    fn merge(&mut self, other: SubcommandClasses) {
+        fn union<T: Ord>(base: &mut Vec<T>, other: Vec<T>) {
+            base.extend(other);
+            base.sort();
+            base.dedup();
+        }
+        union(&mut self.aliases, other.aliases);
+        union(&mut self.classes, other.classes);
+        union(&mut self.not_interactive_with, other.not_interactive_with);
+        union(&mut self.not_streaming_with, other.not_streaming_with);
+        union(&mut self.mutating_with, other.mutating_with);
+    }
+}
+
+/// The classes of a command after looking at its flags
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Classification {
+    pub interactive: bool,
+    pub mutating: bool,
+    pub streaming: bool,
+    /// The subcommand is not in the table, so it may do anything (for example a plugin command)
+    pub unknown: bool,
+}
+
+impl Classification {
+    /// An interactive command needs the terminal, so it can only run one environment at a time, an
+    /// unknown command may be interactive as well. A streaming command never stops, so it always
+    /// runs on all environments at once
+    pub fn requires_sequential_mode(&self) -> bool {
+        !self.streaming && (self.interactive || self.unknown)
+    }
+}
+
+struct Builtin {
+    name: &'static str,
+    aliases: &'static [&'static str],
+    classes: &'static [Class],
+    not_interactive_with: &'static [&'static str],
+    not_streaming_with: &'static [&'static str],
+    mutating_with: &'static [&'static str],
+}
+
+const fn builtin(
+    name: &'static str,
+    aliases: &'static [&'static str],
+    classes: &'static [Class],
+) -> Builtin {
+    Builtin {
+        name,
+        aliases,
+        classes,
+        not_interactive_with: &[],
+        not_streaming_with: &[],
+        mutating_with: &[],
+    }
+}
+
+/// A subcommand that asks for confirmation unless it is forced
+const fn forced(name: &'static str, aliases: &'static [&'static str]) -> Builtin {
+    Builtin {
+        name,
+        aliases,
+        classes: &[Class::Interactive, Class::Mutating],
+        not_interactive_with: &["-f"],
+        not_streaming_with: &[],
+        mutating_with: &[],
+    }
+}
+
+/// A subcommand that only reads from the environment (or only changes the local cf config)
+const fn read_only(name: &'static str, aliases: &'static [&'static str]) -> Builtin {
+    builtin(name, aliases, &[])
+}
+
+const MUTATING: &[Class] = &[Class::Mutating];
+
+const BUILTIN_SUBCOMMANDS: &[Builtin] = &[
+    builtin("login", &["l"], &[Class::Interactive]),
+    builtin("passwd", &["pw"], &[Class::Interactive]),
+    Builtin {
+        name: "ssh",
+        aliases: &[],
+        classes: &[Class::Interactive],
+        not_interactive_with: &["-c", "--command", "-N"],
+        not_streaming_with: &[],
+        mutating_with: &[],
+    },
+    Builtin {
+        name: "logs",
+        aliases: &[],
+        classes: &[Class::Streaming],
+        not_interactive_with: &[],
+        not_streaming_with: &["--recent"],
+        mutating_with: &[],
+    },
+    Builtin {
+        name: "curl",
+        aliases: &[],
+        classes: &[],
+        not_interactive_with: &[],
+        not_streaming_with: &[],
+        mutating_with: &["-X POST", "-X PUT", "-X PATCH", "-X DELETE", "-d"],
+    },
+    Builtin {
+        name: "upgrade-service",
+        aliases: &[],
+        classes: &[Class::Interactive, Class::Mutating],
+        not_interactive_with: &["-f", "--force"],
+        not_streaming_with: &[],
+        mutating_with: &[],
+    },
+    forced("delete", &["d"]),
+    forced("delete-service", &["ds"]),
+    forced("delete-service-key", &["dsk"]),
+    forced("delete-route", &[]),
+    forced("delete-orphaned-routes", &[]),
+    forced("delete-space", &[]),
+    forced("delete-org", &[]),
+    forced("delete-buildpack", &[]),
+    forced("delete-private-domain", &[]),
+    forced("delete-shared-domain", &[]),
+    forced("delete-service-broker", &[]),
+    forced("delete-security-group", &[]),
+    forced("delete-user", &[]),
+    forced("delete-quota", &[]),
+    forced("delete-org-quota", &[]),
+    forced("delete-space-quota", &[]),
+    forced("delete-isolation-segment", &[]),
+    forced("delete-feature-flag", &[]),
+    forced("purge-service-instance", &[]),
+    forced("purge-service-offering", &[]),
+    forced("unshare-service", &[]),
+    builtin("push", &["p"], MUTATING),
+    builtin("scale", &[], MUTATING),
+    builtin("stop", &["sp"], MUTATING),
+    builtin("start", &["st"], MUTATING),
+    builtin("restart", &["rs"], MUTATING),
+    builtin("restage", &["rg"], MUTATING),
+    builtin("rename", &[], MUTATING),
+    builtin("set-env", &["se"], MUTATING),
+    builtin("unset-env", &[], MUTATING),
+    builtin("bind-service", &["bs"], MUTATING),
+    builtin("unbind-service", &["us"], MUTATING),
+    builtin("bind-route-service", &["brs"], MUTATING),
+    builtin("unbind-route-service", &["urs"], MUTATING),
+    builtin("map-route", &[], MUTATING),
+    builtin("unmap-route", &[], MUTATING),
+    builtin("create-route", &[], MUTATING),
+    builtin("create-service", &["cs"], MUTATING),
+    builtin("update-service", &[], MUTATING),
+    builtin("rename-service", &[], MUTATING),
+    builtin("create-user-provided-service", &["cups"], MUTATING),
+    builtin("update-user-provided-service", &["uups"], MUTATING),
+    builtin("create-service-key", &["csk"], MUTATING),
+    builtin("share-service", &[], MUTATING),
+    builtin("run-task", &["rt"], MUTATING),
+    builtin("rollback", &[], MUTATING),
+    builtin("cancel-deployment", &[], MUTATING),
+    builtin("restart-app-instance", &[], MUTATING),
+    builtin("create-app", &[], MUTATING),
+    builtin("apply-manifest", &[], MUTATING),
+    builtin("set-health-check", &[], MUTATING),
+    builtin("set-droplet", &[], MUTATING),
+    builtin("create-space", &[], MUTATING),
+    builtin("create-org", &["co"], MUTATING),
+    builtin("create-buildpack", &[], MUTATING),
+    builtin("update-buildpack", &[], MUTATING),
+    builtin("enable-ssh", &[], MUTATING),
+    builtin("disable-ssh", &[], MUTATING),
+    builtin("set-space-role", &[], MUTATING),
+    builtin("unset-space-role", &[], MUTATING),
+    builtin("set-org-role", &[], MUTATING),
+    builtin("unset-org-role", &[], MUTATING),
+    read_only("help", &["h"]),
+    read_only("version", &[]),
+    read_only("api", &[]),
+    read_only("auth", &[]),
+    read_only("logout", &["lo"]),
+    read_only("target", &["t"]),
+    read_only("config", &[]),
+    read_only("oauth-token", &[]),
+    read_only("ssh-code", &[]),
+    read_only("plugins", &[]),
+    read_only("apps", &["a"]),
+    read_only("app", &[]),
+    read_only("env", &["e"]),
+    read_only("events", &[]),
+    read_only("tasks", &[]),
+    read_only("droplets", &[]),
+    read_only("packages", &[]),
+    read_only("revisions", &[]),
+    read_only("get-health-check", &[]),
+    read_only("ssh-enabled", &[]),
+    read_only("space-ssh-allowed", &[]),
+    read_only("create-app-manifest", &[]),
+    read_only("services", &["s"]),
+    read_only("service", &[]),
+    read_only("service-keys", &["sk"]),
+    read_only("service-key", &[]),
+    read_only("marketplace", &["m"]),
+    read_only("service-brokers", &[]),
+    read_only("service-access", &[]),
+    read_only("routes", &["r"]),
+    read_only("domains", &[]),
+    read_only("check-route", &[]),
+    read_only("router-groups", &[]),
+    read_only("network-policies", &[]),
+    read_only("orgs", &["o"]),
+    read_only("org", &[]),
+    read_only("spaces", &[]),
+    read_only("space", &[]),
+    read_only("org-users", &[]),
+    read_only("space-users", &[]),
+    read_only("buildpacks", &[]),
+    read_only("stacks", &[]),
+    read_only("stack", &[]),
+    read_only("feature-flags", &[]),
+    read_only("feature-flag", &[]),
+    read_only("security-groups", &[]),
+    read_only("security-group", &[]),
+    read_only("quotas", &[]),
+    read_only("quota", &[]),
+    read_only("space-quotas", &[]),
+    read_only("space-quota", &[]),
+    read_only("isolation-segments", &[]),
+    read_only("running-environment-variable-group", &["revg"]),
+    read_only("staging-environment-variable-group", &["sevg"]),
+];
+
+/// The built-in subcommands extended with the subcommands from the settings, a subcommand in the
+/// settings adds to the built-in subcommand with the same name but can not remove anything from it
+pub fn subcommands(settings: &Settings) -> BTreeMap<String, SubcommandClasses> {
+    let to_strings = |values: &[&str]| values.iter().map(|value| value.to_string()).collect();
+    let mut result = BUILTIN_SUBCOMMANDS
+        .iter()
+        .map(|builtin| {
+            (
+                builtin.name.to_string(),
+                SubcommandClasses {
+                    aliases: to_strings(builtin.aliases),
+                    classes: builtin.classes.to_vec(),
+                    not_interactive_with: to_strings(builtin.not_interactive_with),
+                    not_streaming_with: to_strings(builtin.not_streaming_with),
+                    mutating_with: to_strings(builtin.mutating_with),
+                },
+            )
+        })
+        .collect::<BTreeMap<String, SubcommandClasses>>();
+    for (name, subcommand) in &settings.subcommands {
+        result
+            .entry(name.clone())
+            .or_default()
+            .merge(subcommand.clone());
+    }
+    result
+}
+
+/// The cf subcommand of a command, the global flags of cf (for example "-v") are skipped
+pub fn parse_subcommand(command: &[String]) -> Option<String> {
+    command
+        .iter()
+        .find(|arg| !arg.starts_with('-'))
+        .map(|arg| arg.to_lowercase())
+}
+
+/// Classify a command by its subcommand (or an alias of it) and its flags, an unknown subcommand
+/// has no classes but is marked as unknown
+pub fn classify(settings: &Settings, command: &[String]) -> Classification {
+    let Some(name) = parse_subcommand(command) else {
+        return Classification::default();
+    };
+    let subcommands = subcommands(settings);
+    let Some(subcommand) = subcommands.get(&name).or_else(|| {
+        subcommands
+            .values()
+            .find(|subcommand| subcommand.aliases.contains(&name))
+    }) else {
+        return Classification {
+            unknown: true,
+            ..Default::default()
+        };
+    };
+    let has_flag = |flags: &[String]| flags.iter().any(|flag| has_flag(command, flag));
+    Classification {
+        interactive: subcommand.classes.contains(&Class::Interactive)
+            && !has_flag(&subcommand.not_interactive_with),
+        mutating: subcommand.classes.contains(&Class::Mutating)
+            || has_flag(&subcommand.mutating_with),
+        streaming: subcommand.classes.contains(&Class::Streaming)
+            && !has_flag(&subcommand.not_streaming_with),
+        unknown: false,
+    }
+}
+
+/// Whether the command has the flag, a flag with a value ("-X POST") matches "-X POST", "-X=POST"
+/// and "-XPOST" with the value in any case
+fn has_flag(command: &[String], flag: &str) -> bool {
+    let Some((name, value)) = flag.split_once(' ') else {
+        return command
+            .iter()
+            .any(|arg| arg == flag || arg.starts_with(&format!("{}=", flag)));
+    };
+    let value = value.to_lowercase();
+    command.iter().enumerate().any(|(index, arg)| {
+        let next = command.get(index + 1).map(|next| next.to_lowercase());
+        match arg.strip_prefix(name) {
+            Some("") => next.as_ref() == Some(&value),
+            Some(rest) => rest.trim_start_matches('=').to_lowercase() == value,
+            None => false,
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse_subcommand() {
+        assert_eq!(
+            parse_subcommand(&strings(&["-v", "Delete", "my-app"])),
+            Some(String::from("delete"))
+        );
+        assert_eq!(parse_subcommand(&strings(&["--help"])), None);
+        assert_eq!(parse_subcommand(&[]), None);
+    }
+
+    #[test]
+    fn test_classify_uses_subcommand_instead_of_arguments() {
+        let settings = Settings::default();
+        assert_eq!(
+            classify(&settings, &strings(&["logs", "ssh-gateway", "--recent"])),
+            Classification::default()
+        );
+        assert_eq!(
+            classify(&settings, &strings(&["app", "delete-me"])),
+            Classification::default()
+        );
+    }
+
+    #[test]
+    fn test_classify_flags_and_aliases() {
+        let settings = Settings::default();
+        let delete = classify(&settings, &strings(&["d", "my-app"]));
+        assert!(delete.interactive && delete.mutating);
+        let forced = classify(&settings, &strings(&["delete", "my-app", "-f"]));
+        assert!(!forced.interactive && forced.mutating);
+        assert!(classify(&settings, &strings(&["ssh", "my-app"])).requires_sequential_mode());
+        assert!(
+            !classify(&settings, &strings(&["ssh", "my-app", "-c", "ls"]))
+                .requires_sequential_mode()
+        );
+        assert!(
+            !classify(&settings, &strings(&["ssh", "my-app", "--command=ls"]))
+                .requires_sequential_mode()
+        );
+        assert!(classify(&settings, &strings(&["logs", "my-app"])).streaming);
+        assert!(!classify(&settings, &strings(&["logs", "my-app"])).requires_sequential_mode());
+        for subcommand in [
+            "delete-quota",
+            "delete-org-quota",
+            "delete-space-quota",
+            "delete-isolation-segment",
+            "delete-feature-flag",
+        ] {
+            let delete = classify(&settings, &strings(&[subcommand, "name"]));
+            assert!(delete.requires_sequential_mode() && delete.mutating && !delete.unknown);
+        }
+        assert!(classify(&settings, &strings(&["P", "my-app"])).mutating);
+    }
+
+    #[test]
+    fn test_classify_unknown_subcommands() {
+        let settings = Settings::default();
+        assert!(classify(&settings, &strings(&["my-plugin-command", "my-app"])).unknown);
+        assert!(
+            classify(&settings, &strings(&["my-plugin-command", "my-app"]))
+                .requires_sequential_mode()
+        );
+        assert!(!classify(&settings, &strings(&["apps"])).unknown);
+        assert!(!classify(&settings, &strings(&["a"])).unknown);
+        assert!(!classify(&settings, &strings(&["--help"])).unknown);
+    }
+
+    #[test]
+    fn test_classify_with_subcommands_from_settings() {
+        let settings = Settings {
+            subcommands: BTreeMap::from([
+                (
+                    String::from("deploy-app"),
+                    SubcommandClasses {
+                        aliases: strings(&["da"]),
+                        classes: vec![Class::Interactive, Class::Mutating],
+                        not_interactive_with: strings(&["--force"]),
+                        ..Default::default()
+                    },
+                ),
+                (
+                    String::from("delete"),
+                    SubcommandClasses {
+                        aliases: strings(&["rm"]),
+                        ..Default::default()
+                    },
+                ),
+            ]),
+            ..Default::default()
+        };
+        let deploy = classify(&settings, &strings(&["da", "my-app"]));
+        assert!(deploy.interactive && deploy.mutating);
+        assert!(!classify(&settings, &strings(&["deploy-app", "--force"])).interactive);
+        let delete = classify(&settings, &strings(&["rm", "my-app"]));
+        assert!(delete.interactive && delete.mutating);
+        assert!(classify(&settings, &strings(&["d", "my-app", "-f"])).mutating);
+    }
+
+    #[test]
+    fn test_classify_mutating_subcommands() {
+        let settings = Settings::default();
+        for command in [
+            &["cups", "my-service", "-p", "{}"][..],
+            &["uups", "my-service", "-p", "{}"],
+            &["upgrade-service", "my-service", "-f"],
+            &["rename-service", "old", "new"],
+            &["enable-ssh", "my-app"],
+            &["disable-ssh", "my-app"],
+            &["create-buildpack", "my-buildpack", "bp.zip", "1"],
+            &["update-buildpack", "my-buildpack", "--lock"],
+            &["curl", "-X", "DELETE", "/v3/apps/guid"],
+            &["curl", "/v3/apps", "-X", "post", "-d", "{}"],
+            &["curl", "-XPATCH", "/v3/apps/guid"],
+            &["curl", "-X=PUT", "/v3/apps/guid"],
+            &["curl", "/v3/apps", "-d", "{}"],
+        ] {
+            assert!(
+                classify(&settings, &strings(command)).mutating,
+                "{:?}",
+                command
+            );
+        }
+        assert!(classify(&settings, &strings(&["upgrade-service", "my-service"])).interactive);
+        assert!(!classify(&settings, &strings(&["curl", "/v3/apps"])).mutating);
+        assert!(!classify(&settings, &strings(&["curl", "-X", "GET", "/v3/apps"])).mutating);
+    }
+}
@@ -0,0 +1,582 @@
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+use ratatui::backend::Backend;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Borders, Paragraph};
+use ratatui::{Frame, Terminal};
+use tokio::sync::mpsc;
+
+use crate::alias::expand;
+use crate::exec::{known_environments, spawn_parallel, OutputLine};
+use crate::options::Options;
+use crate::settings::Settings;
+use crate::subcommand::classify;
+
+/// Lines that are kept per pane, older lines are dropped
+const MAX_LINES: usize = 10_000;
+const PAGE: usize = 10;
+/// Output lines that are taken before the next draw, so a stream that never stops still gets drawn
+const MAX_OUTPUT_BATCH: usize = 1_000;
+
+/// The output of one environment
+#[derive(Debug, Default)]
+pub struct Pane {
+    pub env_name: String,
+    lines: Vec<(String, bool)>,
+    /// Lines that arrived while the pane was paused
+    pending: Vec<(String, bool)>,
+    /// Number of lines scrolled up from the bottom, 0 follows the output
+    scroll: usize,
+    pub paused: bool,
+    pub search: Option<String>,
+}
+
+impl Pane {
+    fn new(env_name: &str) -> Pane {
+        Pane {
+            env_name: env_name.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn push(&mut self, line: String, stderr: bool) {
+        if self.paused {
+            self.pending.push((line, stderr));
+            return;
+        }
+        if self.scroll > 0 && self.matches(&line) {
+            self.scroll += 1;
+        }
+        self.lines.push((line, stderr));
+        if self.lines.len() > MAX_LINES {
+            self.lines.drain(..self.lines.len() - MAX_LINES);
+        }
+    }
+
+    fn toggle_pause(&mut self) {
+        self.paused = !self.paused;
+        if !self.paused {
+            for (line, stderr) in std::mem::take(&mut self.pending) {
+                self.push(line, stderr);
+            }
+        }
+    }
+
+    fn matches(&self, line: &str) -> bool {
+        match &self.search {
+            Some(some) => line.to_lowercase().contains(&some.to_lowercase()),
+            None => true,
+        }
+    }
+
+    /// The lines that match the search of this pane
+    pub fn visible_lines(&self) -> Vec<&(String, bool)> {
+        self.lines
+            .iter()
+            .filter(|(line, _)| self.matches(line))
+            .collect()
+    }
+
+    fn scroll_up(&mut self, lines: usize) {
+        self.scroll = (self.scroll + lines).min(self.visible_lines().len());
+    }
+
+    fn scroll_down(&mut self, lines: usize) {
+        self.scroll = self.scroll.saturating_sub(lines);
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Input {
+    Normal,
+    Search(String),
+}
+
+/// The state of the tui, a pane per environment of which one has the focus
+#[derive(Debug)]
+pub struct App {
+    pub panes: Vec<Pane>,
+    pub focused: usize,
+    pub fullscreen: bool,
+    pub quit: bool,
+    input: Input,
+}
+
+impl App {
+    pub fn new(env_names: &[String]) -> App {
+        App {
+            panes: env_names.iter().map(|name| Pane::new(name)).collect(),
+            focused: 0,
+            fullscreen: false,
+            quit: false,
+            input: Input::Normal,
+        }
+    }
+
+    pub fn push_output(&mut self, output: OutputLine) {
+        if let Some(some) = self
+            .panes
+            .iter_mut()
+            .find(|pane| pane.env_name == output.env_name)
+        {
+            some.push(output.line, output.stderr);
+        }
+    }
+
+    pub fn handle_event(&mut self, event: Event) {
+        if let Event::Key(key) = event {
+            if key.kind == KeyEventKind::Press {
+                self.handle_key(key);
+            }
+        }
+    }
+
+    pub fn handle_key(&mut self, key: KeyEvent) {
+        if key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c') {
+            self.quit = true;
+            return;
+        }
+        let count = self.panes.len().max(1);
+        let pane = &mut self.panes[self.focused];
+        if let Input::Search(query) = &mut self.input {
+            match key.code {
+                KeyCode::Char(c) => query.push(c),
+                KeyCode::Backspace => {
+                    query.pop();
+                }
+                KeyCode::Enter => {
+                    pane.search = Some(query.clone()).filter(|query| !query.is_empty());
+                    pane.scroll = 0;
+                    self.input = Input::Normal;
+                }
+                KeyCode::Esc => self.input = Input::Normal,
+                _ => {}
+            }
+            return;
+        }
+        match key.code {
+            KeyCode::Char('q') => self.quit = true,
+            KeyCode::Tab | KeyCode::Right | KeyCode::Char('l') => {
+                self.focused = (self.focused + 1) % count
+            }
+            KeyCode::BackTab | KeyCode::Left | KeyCode::Char('h') => {
+                self.focused = (self.focused + count - 1) % count
+            }
+            KeyCode::Up | KeyCode::Char('k') => pane.scroll_up(1),
+            KeyCode::Down | KeyCode::Char('j') => pane.scroll_down(1),
+            KeyCode::PageUp => pane.scroll_up(PAGE),
+            KeyCode::PageDown => pane.scroll_down(PAGE),
+            KeyCode::End | KeyCode::Char('G') => pane.scroll = 0,
+            KeyCode::Char('p') | KeyCode::Char(' ') => pane.toggle_pause(),
+            KeyCode::Char('/') => {
+                self.input = Input::Search(pane.search.clone().unwrap_or_default())
+            }
+            KeyCode::Char('f') | KeyCode::Enter => self.fullscreen = !self.fullscreen,
+            KeyCode::Esc if self.fullscreen => self.fullscreen = false,
+            KeyCode::Esc => {
+                pane.search = None;
+                pane.scroll = 0;
+            }
+            _ => {}
+        }
+    }
+}
+
+pub fn render(frame: &mut Frame, app: &App) {
+    let [body, status] =
+        Layout::vertical([Constraint::Min(0), Constraint::Length(1)]).areas(frame.area());
+    if app.fullscreen {
+        if let Some(some) = app.panes.get(app.focused) {
+            render_pane(frame, some, body, true);
+        }
+    } else {
+        for (index, area) in grid(body, app.panes.len()).into_iter().enumerate() {
+            render_pane(frame, &app.panes[index], area, index == app.focused);
+        }
+    }
+    let status_line = match &app.input {
+        Input::Search(query) => format!("search {}: {}_", app.panes[app.focused].env_name, query),
+        Input::Normal => String::from(
+            "q quit | tab next pane | up/down scroll | p pause | / search | f full screen",
+        ),
+    };
+    frame.render_widget(
+        Paragraph::new(status_line).style(Style::default().add_modifier(Modifier::REVERSED)),
+        status,
+    );
+}
+
+/// Divide the area into rows of panes, as close to a square as possible
+fn grid(area: Rect, count: usize) -> Vec<Rect> {
+    let columns = (1..=count.max(1))
+        .find(|columns| columns * columns >= count)
+        .unwrap_or(1);
+    let rows = count.div_ceil(columns);
+    Layout::vertical(vec![Constraint::Ratio(1, rows.max(1) as u32); rows])
+        .split(area)
+        .iter()
+        .enumerate()
+        .flat_map(|(row, area)| {
+            let in_row = columns.min(count - row * columns);
+            Layout::horizontal(vec![Constraint::Ratio(1, in_row as u32); in_row])
+                .split(*area)
+                .to_vec()
+        })
+        .collect()
+}
+
+fn render_pane(frame: &mut Frame, pane: &Pane, area: Rect, focused: bool) {
+    let mut title = format!(" {} ", pane.env_name);
+    if pane.paused {
+        title.push_str(&format!("[paused, {} new] ", pane.pending.len()));
+    }
+    if let Some(some) = &pane.search {
+        title.push_str(&format!("[search: {}] ", some));
+    }
+    if pane.scroll > 0 {
+        title.push_str(&format!("[scrolled up {}] ", pane.scroll));
+    }
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .title(title)
+        .border_style(if focused {
+            Style::default().fg(Color::Yellow)
+        } else {
+            Style::default()
+        });
+    let height = block.inner(area).height as usize;
+    let lines = pane.visible_lines();
+    let end = lines.len() - pane.scroll.min(lines.len());
+    let start = end.saturating_sub(height);
+    let text = lines[start..end]
+        .iter()
+        .map(|(line, stderr)| highlight(line, *stderr, pane.search.as_deref()))
+        .collect::<Vec<Line>>();
+    frame.render_widget(Paragraph::new(text).block(block), area);
+}
+
+fn highlight<'a>(line: &'a str, stderr: bool, search: Option<&str>) -> Line<'a> {
+    let style = if stderr {
+        Style::default().fg(Color::Red)
+    } else {
+        Style::default()
+    };
+    let lowercase = line.to_lowercase();
+    let Some(search) = search
+        .map(str::to_lowercase)
+        .filter(|search| !search.is_empty() && lowercase.len() == line.len())
+    else {
+        return Line::styled(line, style);
+    };
+    let mut spans = Vec::new();
+    let mut position = 0;
+    for (start, found) in lowercase.match_indices(&search) {
+        spans.push(Span::styled(&line[position..start], style));
+        spans.push(Span::styled(
+            &line[start..start + found.len()],
+            style.add_modifier(Modifier::REVERSED),
+        ));
+        position = start + found.len();
+    }
+    spans.push(Span::styled(&line[position..], style));
+    Line::from(spans)
+}
+
+/// Draw the app until it is closed, the output and the events come from channels so any backend
+/// can be used. Events are polled first, so output that never stops can not starve a quit
+pub async fn run_app<B: Backend>(
+    terminal: &mut Terminal<B>,
+    app: &mut App,
+    outputs: &mut mpsc::UnboundedReceiver<OutputLine>,
+    events: &mut mpsc::UnboundedReceiver<Event>,
+) -> Result<()> {
+    while !app.quit {
+        terminal.draw(|frame| render(frame, app))?;
+        tokio::select! {
+            biased;
+            Some(event) = events.recv() => app.handle_event(event),
+            Some(output) = outputs.recv() => {
+                app.push_output(output);
+                for _ in 1..MAX_OUTPUT_BATCH {
+                    match outputs.try_recv() {
+                        Ok(output) => app.push_output(output),
+                        Err(_) => break,
+                    }
+                }
+            }
+            else => break,
+        }
+    }
+    Ok(())
+}
+
+/// Execute the command on every environment and show the output in a pane per environment
+pub async fn tui(
+    settings: &Settings,
+    options: Arc<Options>,
+    names: &str,
+    command: Arc<Vec<String>>,
+    original_cf_home: Arc<PathBuf>,
+    mcf_folder: Arc<PathBuf>,
+) -> Result<()> {
+    let command = Arc::new(expand(settings, &command)?);
+    if classify(settings, &command).interactive {
+        bail!(
+            "mcf: {:?} is interactive and can not run in the tui, use \"mcf exec\" instead",
+            command.join(" ")
+        );
+    }
+    let environments = known_environments(names, settings)?;
+    let mut app = App::new(
+        &environments
+            .iter()
+            .map(|env| env.name.clone())
+            .collect::<Vec<String>>(),
+    );
+    let (output_sender, mut outputs) = mpsc::unbounded_channel();
+    let mut tasks = spawn_parallel(
+        environments,
+        options,
+        command,
+        original_cf_home,
+        mcf_folder,
+        output_sender,
+    );
+    let (event_sender, mut events) = mpsc::unbounded_channel();
+    let stop = Arc::new(AtomicBool::new(false));
+    let reader = {
+        let stop = stop.clone();
+        std::thread::spawn(move || read_events(event_sender, stop))
+    };
+    let mut terminal = ratatui::init();
+    let result = run_app(&mut terminal, &mut app, &mut outputs, &mut events).await;
+    ratatui::restore();
+    stop.store(true, Ordering::Relaxed);
+    let _ = reader.join();
+    // dropping the tasks kills the cf processes that are still running
+    tasks.abort_all();
+    while tasks.join_next().await.is_some() {}
+    result
+}
+
+fn read_events(sender: mpsc::UnboundedSender<Event>, stop: Arc<AtomicBool>) {
+    while !stop.load(Ordering::Relaxed) {
+        match event::poll(Duration::from_millis(100)) {
+            Ok(true) => {
+                let Ok(some) = event::read() else { break };
+                if sender.send(some).is_err() {
+                    break;
+                }
+            }
+            Ok(false) => {}
+            Err(_) => break,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ratatui::backend::TestBackend;
+
+    use super::*;
+
+    fn app_with_lines() -> App {
+        let mut app = App::new(&[String::from("p01"), String::from("p02")]);
+        for (env_name, line) in [
+            ("p01", "2022-09-02T15:53:16.16+0200 [RTR/1] GET /health"),
+            ("p02", "2022-09-02T15:53:17.16+0200 [APP/0] started"),
+            ("p01", "2022-09-02T15:53:18.16+0200 [APP/0] error: timeout"),
+        ] {
+            app.push_output(OutputLine {
+                env_name: env_name.to_string(),
+                line: line.to_string(),
+                stderr: false,
+                attempt: 0,
+            });
+        }
+        app
+    }
+
+    fn key(code: KeyCode) -> KeyEvent {
+        KeyEvent::new(code, KeyModifiers::NONE)
+    }
+
+    fn screen(app: &App, width: u16, height: u16) -> String {
+        let mut terminal = Terminal::new(TestBackend::new(width, height)).unwrap();
+        terminal.draw(|frame| render(frame, app)).unwrap();
+        let buffer = terminal.backend().buffer();
+        (0..height)
+            .map(|y| {
+                (0..width)
+                    .map(|x| buffer[(x, y)].symbol())
+                    .collect::<String>()
+            })
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+
+    #[test]
+    fn test_render_pane_per_environment() {
+        let screen = screen(&app_with_lines(), 120, 10);
+        assert!(screen.contains(" p01 "));
+        assert!(screen.contains(" p02 "));
+        assert!(screen.contains("[RTR/1] GET /health"));
+        assert!(screen.contains("[APP/0] started"));
+        assert!(screen.contains("q quit"));
+    }
+
+    #[test]
+    fn test_search_filters_focused_pane() {
+        let mut app = app_with_lines();
+        for code in [
+            KeyCode::Char('/'),
+            KeyCode::Char('E'),
+            KeyCode::Char('r'),
+            KeyCode::Char('r'),
+            KeyCode::Enter,
+        ] {
+            app.handle_key(key(code));
+        }
+        assert_eq!(app.panes[0].search, Some(String::from("Err")));
+        assert_eq!(app.panes[0].visible_lines().len(), 1);
+        assert_eq!(app.panes[1].visible_lines().len(), 1);
+        let screen = screen(&app, 120, 10);
+        assert!(screen.contains("[search: Err]"));
+        assert!(!screen.contains("GET /health"));
+        app.handle_key(key(KeyCode::Esc));
+        assert_eq!(app.panes[0].search, None);
+    }
+
+    #[test]
+    fn test_pause_keeps_new_lines_until_resumed() {
+        let mut app = app_with_lines();
+        app.handle_key(key(KeyCode::Tab));
+        app.handle_key(key(KeyCode::Char('p')));
+        app.push_output(OutputLine {
+            env_name: String::from("p02"),
+            line: String::from("new line"),
+            stderr: true,
+            attempt: 0,
+        });
+        assert_eq!(app.panes[1].visible_lines().len(), 1);
+        assert!(screen(&app, 120, 10).contains("[paused, 1 new]"));
+        app.handle_key(key(KeyCode::Char('p')));
+        assert_eq!(app.panes[1].visible_lines().len(), 2);
+    }
+
+    #[test]
+    fn test_fullscreen_shows_only_focused_pane() {
+        let mut app = app_with_lines();
+        app.handle_key(key(KeyCode::Char('f')));
+        let screen = screen(&app, 120, 10);
+        assert!(screen.contains(" p01 "));
+        assert!(!screen.contains(" p02 "));
+        app.handle_key(key(KeyCode::Esc));
+        assert!(!app.fullscreen);
+    }
+
+    #[test]
+    fn test_scroll_stays_in_place_when_lines_arrive() {
+        let mut app = app_with_lines();
+        app.handle_key(key(KeyCode::Up));
+        app.push_output(OutputLine {
+            env_name: String::from("p01"),
+            line: String::from("newest"),
+            stderr: false,
+            attempt: 0,
+        });
+        let screen = screen(&app, 120, 10);
+        assert!(screen.contains("[scrolled up 2]"));
+        assert!(!screen.contains("newest"));
+        app.handle_key(key(KeyCode::End));
+        assert_eq!(app.panes[0].scroll, 0);
+    }
+
+    #[test]
+    fn test_grid() {
+        let area = Rect::new(0, 0, 100, 40);
+        assert_eq!(grid(area, 1), vec![area]);
+        let panes = grid(area, 3);
+        assert_eq!(panes.len(), 3);
+        assert_eq!(panes[2].width, 100);
+        assert_eq!(grid(area, 4).len(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_run_app_until_quit() {
+        let mut app = App::new(&[String::from("p01")]);
+        let mut terminal = Terminal::new(TestBackend::new(80, 10)).unwrap();
+        let (output_sender, mut outputs) = mpsc::unbounded_channel();
+        let (event_sender, mut events) = mpsc::unbounded_channel();
+        output_sender
+            .send(OutputLine {
+                env_name: String::from("p01"),
+                line: String::from("hello"),
+                stderr: false,
+                attempt: 0,
+            })
+            .unwrap();
+        event_sender
+            .send(Event::Key(key(KeyCode::Char('q'))))
+            .unwrap();
+        drop(event_sender);
+        run_app(&mut terminal, &mut app, &mut outputs, &mut events)
+            .await
+            .unwrap();
+        assert!(app.quit);
+        assert!(app.panes[0].visible_lines().is_empty());
+        let (event_sender, mut events) = mpsc::unbounded_channel::<Event>();
+        let mut app = App::new(&[String::from("p01")]);
+        drop(event_sender);
+        drop(output_sender);
+        run_app(&mut terminal, &mut app, &mut outputs, &mut events)
+            .await
+            .unwrap();
+        assert_eq!(app.panes[0].visible_lines().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_run_app_quits_while_output_never_stops() {
+        let mut app = App::new(&[String::from("p01")]);
+        let mut terminal = Terminal::new(TestBackend::new(80, 10)).unwrap();
+        let (output_sender, mut outputs) = mpsc::unbounded_channel();
+        let (event_sender, mut events) = mpsc::unbounded_channel();
+        let chatty = tokio::spawn(async move {
+            loop {
+                let line = OutputLine {
+                    env_name: String::from("p01"),
+                    line: String::from("chatty"),
+                    stderr: false,
+                    attempt: 0,
+                };
+                if output_sender.send(line).is_err() {
+                    break;
+                }
+                tokio::task::yield_now().await;
+            }
+        });
+        tokio::spawn(async move {
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+            event_sender
+                .send(Event::Key(key(KeyCode::Char('q'))))
+                .unwrap();
+        });
+        tokio::time::timeout(
+            std::time::Duration::from_secs(5),
+            run_app(&mut terminal, &mut app, &mut outputs, &mut events),
+        )
+        .await
+        .expect("q was starved by the output")
+        .unwrap();
+        assert!(app.quit);
+        drop(outputs);
+        chatty.await.unwrap();
+    }
+}